@@ -33,6 +33,12 @@ in
 
   environment.systemPackages = [ network-status ];
 
+  # network-status has a font for Latin, Greek and Cyrillic built in, unifont
+  # covers the rest (e.g. CJK hostnames or passwords)
+  environment.etc."network-status.toml".text = ''
+    fonts = ["${pkgs.unifont}/share/fonts"]
+  '';
+
   # Less ipv6 addresses to reduce the noise
   networking.tempAddresses = "disabled";
 
@@ -1,44 +1,29 @@
+//! Picks the built-in logo
+//!
+//! `clan-logo.svg` by default, `NETWORK_STATUS_LOGO=/path/to/logo.svg` (or a
+//! PNG) replaces it. The file is embedded as it is and rasterised at runtime,
+//! at whatever size the layout asks for.
+
 use std::env;
 use std::path::PathBuf;
-use std::process::Command;
 
 fn main() {
-    println!("cargo:rerun-if-changed=clan-logo.svg");
-
-    let out_dir = PathBuf::from(env::var("OUT_DIR").unwrap());
-    let png_path = out_dir.join("clan-logo.png");
-    let raw_path = out_dir.join("clan-logo.rgba");
-
-    // Step 1: Rasterize SVG to PNG
-    let status = Command::new("resvg")
-        .args(&[
-            "clan-logo.svg",
-            png_path.to_str().unwrap(),
-            "--width",
-            "223",
-            "--height",
-            "89",
-        ])
-        .status()
-        .expect("Failed to run resvg");
-
-    if !status.success() {
-        panic!("resvg failed to convert SVG to PNG");
-    }
+    println!("cargo:rerun-if-env-changed=NETWORK_STATUS_LOGO");
 
-    // Step 2: Convert PNG to raw RGBA
-    let status = Command::new("gm")
-        .args(&[
-            "convert",
-            png_path.to_str().unwrap(),
-            "-depth",
-            "8",
-            &format!("rgba:{}", raw_path.display()),
-        ])
-        .status()
-        .expect("Failed to run gm");
+    let manifest_dir = PathBuf::from(env::var("CARGO_MANIFEST_DIR").unwrap());
+    // Relative paths are relative to the crate, like the default
+    let logo = match env::var_os("NETWORK_STATUS_LOGO") {
+        Some(path) if !path.is_empty() => manifest_dir.join(path),
+        _ => manifest_dir.join("clan-logo.svg"),
+    };
+    println!("cargo:rerun-if-changed={}", logo.display());
 
-    if !status.success() {
-        panic!("gm failed to convert PNG to raw bitmap");
+    let data = match std::fs::read(&logo) {
+        Ok(data) => data,
+        Err(e) => panic!("cannot read the logo {}: {} (set NETWORK_STATUS_LOGO to a PNG or SVG)", logo.display(), e),
+    };
+    if !data.starts_with(b"\x89PNG\r\n\x1a\n") && !data.trim_ascii_start().starts_with(b"<") {
+        panic!("the logo {} is neither a PNG nor an SVG", logo.display());
     }
+    println!("cargo:rustc-env=NETWORK_STATUS_LOGO={}", logo.display());
 }
@@ -1,8 +1,8 @@
 { lib
 , rustPlatform
-, resvg
-, graphicsmagick
 , features ? []
+  # PNG or SVG to build in instead of the Clan logo
+, logo ? null
 }:
 
 rustPlatform.buildRustPackage rec {
@@ -15,8 +15,9 @@ rustPlatform.buildRustPackage rec {
     lockFile = ./Cargo.lock;
   };
 
-  # resvg and graphicsmagick needed by build.rs to rasterize logo
-  nativeBuildInputs = [ resvg graphicsmagick ];
+  env = lib.optionalAttrs (logo != null) {
+    NETWORK_STATUS_LOGO = "${logo}";
+  };
 
   # Only build with features if specified
   buildFeatures = features;
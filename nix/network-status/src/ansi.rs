@@ -0,0 +1,354 @@
+//! ANSI escape sequences in status lines
+//!
+//! The status lines are written for a terminal first, so they carry SGR
+//! ("select graphic rendition", `ESC [ ... m`) codes. This turns them into
+//! segments for the framebuffer with colours, bold, underline and inverse
+//! video, using the same palette the installer configures for the Linux
+//! console. Every other escape sequence is stripped.
+
+use crate::{Color, TextSegment};
+
+const ESC: char = '\x1b';
+const BEL: char = '\x07';
+
+/// Tango, like `console.colors` in `image-installer/module.nix`
+pub const PALETTE: [Color; 16] = [
+    rgb(0x000000),
+    rgb(0xCC0000),
+    rgb(0x4E9A06),
+    rgb(0xC4A000),
+    rgb(0x3465A4),
+    rgb(0x75507B),
+    rgb(0x06989A),
+    rgb(0xD3D7CF),
+    rgb(0x555753),
+    rgb(0xEF2929),
+    rgb(0x8AE234),
+    rgb(0xFCE94F),
+    rgb(0x739FCF),
+    rgb(0xAD7FA8),
+    rgb(0x34E2E2),
+    rgb(0xEEEEEC),
+];
+
+const fn rgb(hex: u32) -> Color {
+    Color { r: (hex >> 16) as u8, g: (hex >> 8) as u8, b: hex as u8 }
+}
+
+/// Colour of the 256-colour palette: the 16 above, a 6x6x6 cube and 24 greys
+pub fn indexed(index: u8) -> Color {
+    match index {
+        0..=15 => PALETTE[index as usize],
+        16..=231 => {
+            let level = |n: u8| if n == 0 { 0 } else { 55 + 40 * n };
+            let n = index - 16;
+            Color { r: level(n / 36), g: level(n / 6 % 6), b: level(n % 6) }
+        }
+        232..=255 => {
+            let grey = 8 + 10 * (index - 232);
+            Color { r: grey, g: grey, b: grey }
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Ink {
+    Default,
+    Indexed(u8),
+    Rgb(Color),
+}
+
+/// Graphic rendition set by SGR sequences so far
+#[derive(Clone, Copy, Debug)]
+struct Rendition {
+    foreground: Ink,
+    background: Ink,
+    bold: bool,
+    underline: bool,
+    inverse: bool,
+}
+
+impl Default for Rendition {
+    fn default() -> Self {
+        Rendition {
+            foreground: Ink::Default,
+            background: Ink::Default,
+            bold: false,
+            underline: false,
+            inverse: false,
+        }
+    }
+}
+
+impl Rendition {
+    /// An empty segment drawn this way
+    fn segment(&self, foreground: Color) -> TextSegment {
+        TextSegment {
+            text: String::new(),
+            color: self.color(foreground),
+            background: match self.background {
+                Ink::Default => None,
+                Ink::Indexed(index) => Some(indexed(index)),
+                Ink::Rgb(color) => Some(color),
+            },
+            bold: self.bold,
+            underline: self.underline,
+            inverse: self.inverse,
+        }
+    }
+
+    /// Like the console, bold shows the first eight colours bright
+    fn color(&self, foreground: Color) -> Color {
+        let bright = if self.bold { 8 } else { 0 };
+        match self.foreground {
+            Ink::Default => foreground,
+            Ink::Indexed(index) if index < 8 => PALETTE[(index + bright) as usize],
+            Ink::Indexed(index) => indexed(index),
+            Ink::Rgb(color) => color,
+        }
+    }
+
+    /// Apply the parameters of one SGR sequence, each a list of
+    /// colon-separated sub-parameters
+    fn apply(&mut self, params: &[Vec<u32>]) {
+        let mut params = params.iter();
+        while let Some(param) = params.next() {
+            match param[0] {
+                0 => *self = Rendition::default(),
+                1 => self.bold = true,
+                22 => self.bold = false,
+                // 4:0 is "no underline", 4:1 to 4:5 are its styles
+                4 => self.underline = param.get(1) != Some(&0),
+                21 => self.underline = true,
+                24 => self.underline = false,
+                7 => self.inverse = true,
+                27 => self.inverse = false,
+                code @ 30..=37 => self.foreground = Ink::Indexed((code - 30) as u8),
+                code @ 90..=97 => self.foreground = Ink::Indexed((code - 90 + 8) as u8),
+                39 => self.foreground = Ink::Default,
+                38 => {
+                    if let Some(ink) = extended_color(param, &mut params) {
+                        self.foreground = ink;
+                    }
+                }
+                code @ 40..=47 => self.background = Ink::Indexed((code - 40) as u8),
+                code @ 100..=107 => self.background = Ink::Indexed((code - 100 + 8) as u8),
+                49 => self.background = Ink::Default,
+                48 => {
+                    if let Some(ink) = extended_color(param, &mut params) {
+                        self.background = ink;
+                    }
+                }
+                // Faint, italic, blink, ...
+                _ => {}
+            }
+        }
+    }
+}
+
+/// The colour of a 38 or 48 parameter, either `38:5:n` or `38:2:[id]:r:g:b`
+/// in one parameter, or `38;5;n` and `38;2;r;g;b` taking the next ones
+fn extended_color<'a>(param: &[u32], rest: &mut impl Iterator<Item = &'a Vec<u32>>) -> Option<Ink> {
+    let args: Vec<u32> = if param.len() > 1 {
+        let mut args = param[1..].to_vec();
+        // The colour space id is optional
+        if args.first() == Some(&2) && args.len() >= 5 {
+            args.remove(1);
+        }
+        args
+    } else {
+        let kind = rest.next()?[0];
+        let count = match kind {
+            5 => 1,
+            2 => 3,
+            _ => 0,
+        };
+        std::iter::once(kind).chain(rest.take(count).map(|p| p[0])).collect()
+    };
+    let byte = |n: &u32| u8::try_from(*n).ok();
+    match args.as_slice() {
+        [5, n] => Some(Ink::Indexed(byte(n)?)),
+        [2, r, g, b] => Some(Ink::Rgb(Color { r: byte(r)?, g: byte(g)?, b: byte(b)? })),
+        _ => None,
+    }
+}
+
+/// Split `text` into runs drawn the same way, without escape sequences,
+/// `foreground` is the colour of text without one
+pub fn parse(text: &str, foreground: Color) -> Vec<TextSegment> {
+    let mut segments: Vec<TextSegment> = Vec::new();
+    let mut rendition = Rendition::default();
+    let mut style = rendition.segment(foreground);
+    let mut chars = text.chars().peekable();
+
+    while let Some(ch) = chars.next() {
+        if ch != ESC {
+            match segments.last_mut() {
+                Some(last) if last.same_style(&style) => last.text.push(ch),
+                _ => segments.push(style.with_text(ch)),
+            }
+            continue;
+        }
+
+        match chars.next() {
+            // CSI: parameter bytes, intermediate bytes, then the final byte
+            Some('[') => {
+                let mut params = String::new();
+                let mut last = None;
+                for c in chars.by_ref() {
+                    if ('\x40'..='\x7e').contains(&c) {
+                        last = Some(c);
+                        break;
+                    }
+                    params.push(c);
+                }
+                if last == Some('m') && params.chars().all(|c| c.is_ascii_digit() || c == ';' || c == ':') {
+                    rendition.apply(&sgr_params(&params));
+                    style = rendition.segment(foreground);
+                }
+            }
+            // OSC and other strings, terminated by BEL or ST (ESC \)
+            Some(']' | 'P' | 'X' | '^' | '_') => {
+                while let Some(c) = chars.next() {
+                    if c == BEL || (c == ESC && chars.next_if_eq(&'\\').is_some()) {
+                        break;
+                    }
+                }
+            }
+            // Two-character sequences like ESC c, or a lone ESC at the end
+            _ => {}
+        }
+    }
+    segments
+}
+
+/// `1;38:2::255:0:0` becomes `[[1], [38, 2, 0, 255, 0, 0]]`, empty and
+/// overlong numbers count as 0 and u32::MAX
+fn sgr_params(params: &str) -> Vec<Vec<u32>> {
+    params
+        .split(';')
+        .map(|param| {
+            param
+                .split(':')
+                .map(|n| if n.is_empty() { 0 } else { n.parse().unwrap_or(u32::MAX) })
+                .collect()
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FOREGROUND: Color = PALETTE[7];
+
+    fn colors(text: &str) -> Vec<(String, Color)> {
+        parse(text, FOREGROUND).into_iter().map(|s| (s.text, s.color)).collect()
+    }
+
+    fn seg(text: &str, color: Color) -> (String, Color) {
+        (text.to_string(), color)
+    }
+
+    #[test]
+    fn basic_and_bright_colors() {
+        let fg = FOREGROUND;
+        assert_eq!(colors("plain"), [seg("plain", fg)]);
+        assert_eq!(
+            colors("a\x1b[31mred\x1b[0m b\x1b[92mgreen\x1b[39mc"),
+            [seg("a", fg), seg("red", PALETTE[1]), seg(" b", fg), seg("green", PALETTE[10]), seg("c", fg)]
+        );
+        // Reset variants
+        assert_eq!(colors("\x1b[34mx\x1b[my\x1b[34mx\x1b[00my"), [
+            seg("x", PALETTE[4]),
+            seg("y", fg),
+            seg("x", PALETTE[4]),
+            seg("y", fg),
+        ]);
+    }
+
+    #[test]
+    fn bold_is_bright() {
+        assert_eq!(colors("\x1b[1;31mx"), [seg("x", PALETTE[9])]);
+        assert_eq!(colors("\x1b[31;1mx"), [seg("x", PALETTE[9])]);
+        // Text without a colour keeps the theme's
+        assert_eq!(colors("\x1b[1mx"), [seg("x", FOREGROUND)]);
+        assert_eq!(colors("\x1b[1;33mx\x1b[22my"), [seg("x", PALETTE[11]), seg("y", PALETTE[3])]);
+        // Only the first eight colours have a bright variant
+        assert_eq!(colors("\x1b[1;38;5;100mx"), [seg("x", indexed(100))]);
+    }
+
+    #[test]
+    fn extended_colors() {
+        assert_eq!(colors("\x1b[38;5;196mx"), [seg("x", Color { r: 255, g: 0, b: 0 })]);
+        assert_eq!(colors("\x1b[38;5;244mx"), [seg("x", Color { r: 128, g: 128, b: 128 })]);
+        assert_eq!(colors("\x1b[38;5;3mx"), [seg("x", PALETTE[3])]);
+        assert_eq!(colors("\x1b[38;2;1;2;3mx"), [seg("x", Color { r: 1, g: 2, b: 3 })]);
+        assert_eq!(colors("\x1b[38:2::1:2:3mx"), [seg("x", Color { r: 1, g: 2, b: 3 })]);
+        assert_eq!(colors("\x1b[38:2:1:2:3mx"), [seg("x", Color { r: 1, g: 2, b: 3 })]);
+        assert_eq!(colors("\x1b[38:5:9mx"), [seg("x", PALETTE[9])]);
+        // Arguments of background colours aren't codes, 31 isn't red here
+        assert_eq!(colors("\x1b[48;2;31;32;33mx"), [seg("x", PALETTE[7])]);
+        assert_eq!(colors("\x1b[48;5;31;32mx"), [seg("x", PALETTE[2])]);
+        // Out of range values are ignored
+        assert_eq!(colors("\x1b[38;2;256;0;0mx"), [seg("x", PALETTE[7])]);
+    }
+
+    #[test]
+    fn backgrounds_and_attributes() {
+        let parsed = parse("\x1b[41;4mx\x1b[24;7my\x1b[27;49;1mz\x1b[48;2;1;2;3;104mw", FOREGROUND);
+        let expected = |text: &str, background, bold, underline, inverse| TextSegment {
+            background,
+            bold,
+            underline,
+            inverse,
+            ..TextSegment::new(text, FOREGROUND)
+        };
+        assert_eq!(
+            parsed,
+            [
+                expected("x", Some(PALETTE[1]), false, true, false),
+                expected("y", Some(PALETTE[1]), false, false, true),
+                expected("z", None, true, false, false),
+                // Bold doesn't brighten backgrounds, but 100-107 are bright
+                expected("w", Some(PALETTE[12]), true, false, false),
+            ]
+        );
+        assert!(!parse("\x1b[4:0mx", FOREGROUND)[0].underline);
+        assert!(parse("\x1b[4:3mx", FOREGROUND)[0].underline);
+        assert!(!parse("\x1b[7;0mx", FOREGROUND)[0].inverse);
+    }
+
+    #[test]
+    fn other_sequences_are_stripped() {
+        let fg = FOREGROUND;
+        assert_eq!(colors("a\x1b[2Kb\x1b[1;1Hc\x1b[?25ld"), [seg("abcd", fg)]);
+        assert_eq!(colors("a\x1b]0;title\x07b\x1b]8;;http://x\x1b\\c"), [seg("abc", fg)]);
+        assert_eq!(colors("a\x1bcb\x1b"), [seg("ab", fg)]);
+        // Private SGR-like sequences don't change the colour
+        assert_eq!(colors("\x1b[>31mx"), [seg("x", fg)]);
+    }
+
+    #[test]
+    fn never_panics() {
+        // xorshift, so failures are reproducible
+        let mut state = 0x2545_f491_4f6c_dd1du64;
+        let mut next = move || {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            state
+        };
+        let alphabet: Vec<char> = "\x1b\x07[]\\;:0123456789mKH?>P_ aé漢\u{301}".chars().collect();
+        for _ in 0..20_000 {
+            let len = (next() % 40) as usize;
+            let text: String = (0..len).map(|_| alphabet[(next() % alphabet.len() as u64) as usize]).collect();
+            let segments = parse(&text, FOREGROUND);
+            for segment in &segments {
+                assert!(!segment.text.is_empty() && !segment.text.contains(ESC), "{:?}", text);
+            }
+        }
+        // Numbers no integer holds
+        parse("\x1b[38;5;99999999999999999999m\x1b[38:2:4294967296:1:1mx", FOREGROUND);
+    }
+}
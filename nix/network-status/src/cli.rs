@@ -0,0 +1,434 @@
+//! Command line parsing
+//!
+//! Exit codes: 0 on success, 1 on runtime errors, 2 on invalid usage.
+
+use std::ffi::OsString;
+use std::fmt;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use crate::layout;
+use crate::rotation::Rotation;
+
+pub const USAGE: &str = "\
+Usage: network-status [OPTIONS] [COMMAND]
+
+Show the root password, network addresses and a login QR code on the
+framebuffer and/or the terminal.
+
+Commands:
+  display          Show the status and keep it updated (default)
+  text             Print the status to stdout once and exit
+  json             Print the status as JSON and exit
+  render [OUTPUT]  Render the framebuffer view into a PNG (default: network-status.png)
+  debug-fb         Print information about the framebuffer device
+
+Options:
+  -c, --config <PATH>     Config file (default: $NETWORK_STATUS_CONFIG or /etc/network-status.toml)
+      --fb-device <PATH>  Framebuffer device, overrides the config file
+      --drm-device <PATH> DRM device, overrides the config file
+      --layout <MODE>     Text below or beside the QR code: auto, below or beside
+      --theme <PATH>      Theme file with colours, texts and logo, overrides the config file
+      --rotate <DEGREES>  Rotate the picture clockwise: 0, 90, 180 or 270, overrides the console's rotation
+      --interval <SECS>   Poll interval when change notifications are unavailable
+      --once              Render once and exit instead of watching for changes
+      --no-framebuffer    Do not draw on the framebuffer
+      --no-terminal       Do not print to the terminal
+      --follow            json: keep running and print one line per change
+      --login             json: print the QR code login payload instead of the full status
+  -h, --help              Print this help
+  -V, --version           Print the version
+
+Exit status: 0 on success, 1 on errors, 2 on invalid usage.
+";
+
+/// Options that never take a value
+const SWITCHES: [&str; 9] = [
+    "--help",
+    "--version",
+    "--once",
+    "--no-framebuffer",
+    "--no-terminal",
+    "--follow",
+    "--login",
+    "--debug-fb",
+    "--output-image",
+];
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Command {
+    Display,
+    Text,
+    Json,
+    Render { output: PathBuf },
+    DebugFb,
+}
+
+impl Command {
+    fn name(&self) -> &'static str {
+        match self {
+            Command::Display => "display",
+            Command::Text => "text",
+            Command::Json => "json",
+            Command::Render { .. } => "render",
+            Command::DebugFb => "debug-fb",
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Cli {
+    pub command: Command,
+    pub config: Option<PathBuf>,
+    pub fb_device: Option<PathBuf>,
+    pub drm_device: Option<PathBuf>,
+    pub layout: Option<layout::Mode>,
+    pub theme: Option<PathBuf>,
+    pub rotate: Option<Rotation>,
+    pub interval: Option<Duration>,
+    pub once: bool,
+    pub no_framebuffer: bool,
+    pub no_terminal: bool,
+    pub follow: bool,
+    pub login: bool,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum CliError {
+    Help,
+    Version,
+    Usage(String),
+}
+
+impl CliError {
+    /// Help and version go to stdout and are no failure
+    pub fn exit_code(&self) -> u8 {
+        match self {
+            CliError::Help | CliError::Version => 0,
+            CliError::Usage(_) => 2,
+        }
+    }
+}
+
+impl fmt::Display for CliError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CliError::Help => f.write_str(USAGE),
+            CliError::Version => write!(f, "network-status {}", env!("CARGO_PKG_VERSION")),
+            CliError::Usage(message) => f.write_str(message),
+        }
+    }
+}
+
+/// Parse the arguments following the program name
+pub fn parse<I: IntoIterator<Item = OsString>>(args: I) -> Result<Cli, CliError> {
+    let mut args = args.into_iter();
+    let mut command = None;
+    let mut render_output = None;
+    let mut cli = Cli {
+        command: Command::Display,
+        config: None,
+        fb_device: None,
+        drm_device: None,
+        layout: None,
+        theme: None,
+        rotate: None,
+        interval: None,
+        once: false,
+        no_framebuffer: false,
+        no_terminal: false,
+        follow: false,
+        login: false,
+    };
+
+    while let Some(arg) = args.next() {
+        let arg = arg
+            .into_string()
+            .map_err(|a| CliError::Usage(format!("invalid argument {:?}", a)))?;
+
+        // Accept both `--option value` and `--option=value`
+        let (flag, inline_value) = match arg.split_once('=') {
+            Some((flag, value)) if flag.starts_with("--") => (flag.to_string(), Some(value.to_string())),
+            _ => (arg.clone(), None),
+        };
+        // `--once=false` must not mean `--once`
+        if inline_value.is_some() && SWITCHES.contains(&flag.as_str()) {
+            return Err(CliError::Usage(format!("{} does not take a value", flag)));
+        }
+        let mut value = |name: &str| -> Result<String, CliError> {
+            match inline_value.clone() {
+                Some(v) => Ok(v),
+                None => args
+                    .next()
+                    .and_then(|v| v.into_string().ok())
+                    .ok_or_else(|| CliError::Usage(format!("{} requires a value", name))),
+            }
+        };
+
+        match flag.as_str() {
+            "-h" | "--help" => return Err(CliError::Help),
+            "-V" | "--version" => return Err(CliError::Version),
+            "-c" | "--config" => cli.config = Some(PathBuf::from(value(&flag)?)),
+            "--fb-device" => cli.fb_device = Some(PathBuf::from(value(&flag)?)),
+            "--drm-device" => cli.drm_device = Some(PathBuf::from(value(&flag)?)),
+            "--layout" => {
+                let raw = value(&flag)?;
+                let mode = layout::Mode::from_name(&raw).ok_or_else(|| {
+                    CliError::Usage(format!("invalid layout '{}', expected {}", raw, layout::Mode::NAMES))
+                })?;
+                cli.layout = Some(mode);
+            }
+            "--theme" => cli.theme = Some(PathBuf::from(value(&flag)?)),
+            "--rotate" => {
+                let raw = value(&flag)?;
+                let rotation = Rotation::from_degrees(&raw).ok_or_else(|| {
+                    CliError::Usage(format!("invalid rotation '{}', expected {}", raw, Rotation::NAMES))
+                })?;
+                cli.rotate = Some(rotation);
+            }
+            "--interval" => {
+                let raw = value(&flag)?;
+                let secs: u64 = raw
+                    .parse()
+                    .ok()
+                    .filter(|&s| s > 0)
+                    .ok_or_else(|| CliError::Usage(format!("invalid interval '{}', expected seconds > 0", raw)))?;
+                cli.interval = Some(Duration::from_secs(secs));
+            }
+            "--once" => cli.once = true,
+            "--no-framebuffer" => cli.no_framebuffer = true,
+            "--no-terminal" => cli.no_terminal = true,
+            "--follow" => cli.follow = true,
+            "--login" => cli.login = true,
+            // Spellings from before subcommands existed
+            "--debug-fb" => set_command(&mut command, Command::DebugFb)?,
+            "--output-image" => set_command(&mut command, Command::Render { output: PathBuf::new() })?,
+            f if f.starts_with('-') && f.len() > 1 => {
+                return Err(CliError::Usage(format!("unknown option '{}'", f)));
+            }
+            "display" if command.is_none() => command = Some(Command::Display),
+            "text" if command.is_none() => command = Some(Command::Text),
+            "json" if command.is_none() => command = Some(Command::Json),
+            "render" if command.is_none() => command = Some(Command::Render { output: PathBuf::new() }),
+            "debug-fb" if command.is_none() => command = Some(Command::DebugFb),
+            _ if matches!(command, Some(Command::Render { .. })) && render_output.is_none() => {
+                render_output = Some(PathBuf::from(arg));
+            }
+            _ if command.is_none() => return Err(CliError::Usage(format!("unknown command '{}'", arg))),
+            _ => return Err(CliError::Usage(format!("unexpected argument '{}'", arg))),
+        }
+    }
+
+    cli.command = match command {
+        Some(Command::Render { .. }) => Command::Render {
+            output: render_output.unwrap_or_else(|| PathBuf::from("network-status.png")),
+        },
+        Some(command) => command,
+        None => Command::Display,
+    };
+
+    validate(&cli)?;
+    Ok(cli)
+}
+
+fn set_command(command: &mut Option<Command>, new: Command) -> Result<(), CliError> {
+    if let Some(existing) = command {
+        return Err(CliError::Usage(format!(
+            "'{}' cannot be combined with '{}'",
+            new.name(),
+            existing.name()
+        )));
+    }
+    *command = Some(new);
+    Ok(())
+}
+
+/// Reject options that have no effect for the chosen command
+fn validate(cli: &Cli) -> Result<(), CliError> {
+    let display_only = [
+        ("--drm-device", cli.drm_device.is_some()),
+        ("--once", cli.once),
+        ("--no-framebuffer", cli.no_framebuffer),
+        ("--no-terminal", cli.no_terminal),
+    ];
+    let json_only = [("--follow", cli.follow), ("--login", cli.login)];
+    let uses_framebuffer = matches!(cli.command, Command::Display | Command::DebugFb);
+
+    if cli.command != Command::Display {
+        if let Some((name, _)) = display_only.iter().find(|(_, set)| *set) {
+            return Err(CliError::Usage(format!(
+                "{} is only valid for 'display', not '{}'",
+                name,
+                cli.command.name()
+            )));
+        }
+    }
+    if cli.command != Command::Json {
+        if let Some((name, _)) = json_only.iter().find(|(_, set)| *set) {
+            return Err(CliError::Usage(format!(
+                "{} is only valid for 'json', not '{}'",
+                name,
+                cli.command.name()
+            )));
+        }
+    }
+    if cli.interval.is_some() && !(cli.command == Command::Display || cli.follow) {
+        return Err(CliError::Usage(
+            "--interval is only valid for 'display' and 'json --follow'".to_string(),
+        ));
+    }
+    if cli.fb_device.is_some() && !uses_framebuffer {
+        return Err(CliError::Usage(format!(
+            "--fb-device is not valid for '{}'",
+            cli.command.name()
+        )));
+    }
+    if cli.layout.is_some() && !(uses_framebuffer || matches!(cli.command, Command::Render { .. })) {
+        return Err(CliError::Usage(format!(
+            "--layout is not valid for '{}'",
+            cli.command.name()
+        )));
+    }
+    if cli.rotate.is_some() && !(uses_framebuffer || matches!(cli.command, Command::Render { .. })) {
+        return Err(CliError::Usage(format!(
+            "--rotate is not valid for '{}'",
+            cli.command.name()
+        )));
+    }
+    if cli.theme.is_some() && cli.command == Command::Json {
+        return Err(CliError::Usage("--theme is not valid for 'json'".to_string()));
+    }
+    if cli.no_framebuffer && cli.no_terminal {
+        return Err(CliError::Usage(
+            "--no-framebuffer and --no-terminal together leave nothing to display".to_string(),
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse_args(args: &[&str]) -> Result<Cli, CliError> {
+        parse(args.iter().map(OsString::from))
+    }
+
+    fn command(args: &[&str]) -> Command {
+        parse_args(args).unwrap().command
+    }
+
+    /// The usage error for `args`
+    fn usage(args: &[&str]) -> String {
+        match parse_args(args) {
+            Err(CliError::Usage(message)) => message,
+            other => panic!("{:?}: expected a usage error, got {:?}", args, other),
+        }
+    }
+
+    #[test]
+    fn commands() {
+        assert_eq!(command(&[]), Command::Display);
+        assert_eq!(command(&["display"]), Command::Display);
+        assert_eq!(command(&["text"]), Command::Text);
+        assert_eq!(command(&["json"]), Command::Json);
+        assert_eq!(command(&["render"]), Command::Render { output: PathBuf::from("network-status.png") });
+        assert_eq!(command(&["render", "out.png"]), Command::Render { output: PathBuf::from("out.png") });
+        assert_eq!(command(&["debug-fb"]), Command::DebugFb);
+        // Options may come before the command
+        assert_eq!(command(&["--layout", "beside", "render", "a"]), Command::Render { output: PathBuf::from("a") });
+
+        assert_eq!(usage(&["show"]), "unknown command 'show'");
+        assert_eq!(usage(&["text", "json"]), "unexpected argument 'json'");
+        assert_eq!(usage(&["render", "a.png", "b.png"]), "unexpected argument 'b.png'");
+    }
+
+    #[test]
+    fn legacy_spellings() {
+        assert_eq!(command(&["--debug-fb"]), Command::DebugFb);
+        assert_eq!(command(&["--output-image"]), Command::Render { output: PathBuf::from("network-status.png") });
+        assert_eq!(command(&["--output-image", "out.png"]), Command::Render { output: PathBuf::from("out.png") });
+        assert_eq!(usage(&["--debug-fb", "--output-image"]), "'render' cannot be combined with 'debug-fb'");
+    }
+
+    #[test]
+    fn option_values() {
+        let separate = parse_args(&[
+            "--config", "/etc/a.toml", "--fb-device", "/dev/fb1", "--drm-device", "/dev/dri/card1",
+            "--layout", "below", "--theme", "t.toml", "--rotate", "180", "--interval", "5",
+        ])
+        .unwrap();
+        let inline = parse_args(&[
+            "--config=/etc/a.toml", "--fb-device=/dev/fb1", "--drm-device=/dev/dri/card1",
+            "--layout=below", "--theme=t.toml", "--rotate=180", "--interval=5",
+        ])
+        .unwrap();
+        assert_eq!(separate, inline);
+        assert_eq!(separate.config, Some(PathBuf::from("/etc/a.toml")));
+        assert_eq!(separate.fb_device, Some(PathBuf::from("/dev/fb1")));
+        assert_eq!(separate.drm_device, Some(PathBuf::from("/dev/dri/card1")));
+        assert_eq!(separate.layout, Some(layout::Mode::Below));
+        assert_eq!(separate.theme, Some(PathBuf::from("t.toml")));
+        assert_eq!(separate.rotate, Some(Rotation::UpsideDown));
+        assert_eq!(separate.interval, Some(Duration::from_secs(5)));
+        assert_eq!(parse_args(&["-c", "/etc/a.toml"]).unwrap().config, separate.config);
+        // Only the first `=` splits
+        assert_eq!(parse_args(&["--config=a=b"]).unwrap().config, Some(PathBuf::from("a=b")));
+
+        assert_eq!(usage(&["--config"]), "--config requires a value");
+        assert_eq!(usage(&["--layout", "left"]), "invalid layout 'left', expected auto, below or beside");
+        assert_eq!(usage(&["--rotate=45"]), "invalid rotation '45', expected 0, 90, 180 or 270");
+        assert_eq!(usage(&["--interval", "0"]), "invalid interval '0', expected seconds > 0");
+        assert_eq!(usage(&["--colour"]), "unknown option '--colour'");
+        assert_eq!(usage(&["--colour=red"]), "unknown option '--colour'");
+    }
+
+    #[test]
+    fn switches_take_no_value() {
+        let cli = parse_args(&["--once", "--no-terminal"]).unwrap();
+        assert!(cli.once && cli.no_terminal && !cli.no_framebuffer);
+        assert!(parse_args(&["json", "--follow", "--login"]).unwrap().login);
+
+        assert_eq!(usage(&["--once=false"]), "--once does not take a value");
+        assert_eq!(usage(&["--no-framebuffer=1"]), "--no-framebuffer does not take a value");
+        assert_eq!(usage(&["json", "--follow="]), "--follow does not take a value");
+        assert_eq!(usage(&["--help=me"]), "--help does not take a value");
+        assert_eq!(usage(&["--output-image=out.png"]), "--output-image does not take a value");
+    }
+
+    #[test]
+    fn options_must_apply_to_the_command() {
+        assert_eq!(usage(&["text", "--once"]), "--once is only valid for 'display', not 'text'");
+        assert_eq!(usage(&["json", "--drm-device", "card0"]), "--drm-device is only valid for 'display', not 'json'");
+        assert_eq!(usage(&["text", "--no-framebuffer"]), "--no-framebuffer is only valid for 'display', not 'text'");
+        assert_eq!(usage(&["debug-fb", "--no-terminal"]), "--no-terminal is only valid for 'display', not 'debug-fb'");
+        assert_eq!(usage(&["--follow"]), "--follow is only valid for 'json', not 'display'");
+        assert_eq!(usage(&["text", "--login"]), "--login is only valid for 'json', not 'text'");
+        assert_eq!(usage(&["json", "--interval", "3"]), "--interval is only valid for 'display' and 'json --follow'");
+        assert_eq!(usage(&["render", "--fb-device", "/dev/fb0"]), "--fb-device is not valid for 'render'");
+        assert_eq!(usage(&["text", "--layout", "auto"]), "--layout is not valid for 'text'");
+        assert_eq!(usage(&["json", "--rotate", "90"]), "--rotate is not valid for 'json'");
+        assert_eq!(usage(&["json", "--theme", "t.toml"]), "--theme is not valid for 'json'");
+        assert_eq!(
+            usage(&["--no-framebuffer", "--no-terminal"]),
+            "--no-framebuffer and --no-terminal together leave nothing to display"
+        );
+
+        // The same options where they apply
+        assert!(parse_args(&["json", "--follow", "--interval", "3"]).is_ok());
+        assert!(parse_args(&["debug-fb", "--fb-device", "/dev/fb1", "--rotate", "90"]).is_ok());
+        assert!(parse_args(&["render", "--layout", "beside", "--theme", "t.toml"]).is_ok());
+    }
+
+    #[test]
+    fn exit_codes() {
+        assert_eq!(parse_args(&["--help"]), Err(CliError::Help));
+        assert_eq!(parse_args(&["text", "-h"]), Err(CliError::Help));
+        assert_eq!(parse_args(&["-V"]), Err(CliError::Version));
+        assert_eq!(CliError::Help.exit_code(), 0);
+        assert_eq!(CliError::Version.exit_code(), 0);
+        for args in [&["--bogus"][..], &["--once=yes"], &["nonsense"], &["--config"], &["text", "--once"]] {
+            assert_eq!(parse_args(args).unwrap_err().exit_code(), 2, "{:?}", args);
+        }
+    }
+}
@@ -0,0 +1,500 @@
+//! Runtime configuration
+//!
+//! Read from a small TOML subset: `key = value` pairs where a value is a
+//! quoted string, an integer or an array of strings. Comments start
+//! with `#`. Every key is optional, unset keys keep the built-in defaults:
+//!
+//! ```toml
+//! root_password_file = "/var/shared/root-password"
+//! onion_hostname_file = "/var/lib/tor/onion/hidden-ssh/hostname"
+//! hostname_file = "/etc/hostname"
+//! ssh_dir = "/etc/ssh"
+//! sshd_config_file = "/etc/ssh/sshd_config"
+//! framebuffer = "/dev/fb0"  # default: every /dev/fb*
+//! drm_device = "/dev/dri/card0"  # default: every card in /dev/dri
+//! backends = ["drm", "fbdev"]  # in order of preference, the terminal is used if none works
+//! layout = "auto"  # text "below" or "beside" the QR code, auto picks by aspect ratio
+//! rotate = 90  # clockwise: 0, 90, 180 or 270, default: like the console
+//! font_size = 12  # points, default: derived from the resolution
+//! fonts = ["/run/current-system/sw/share/fonts"]  # fallbacks for e.g. CJK, files or directories
+//! poll_interval = 2  # seconds, only used when change notifications are unavailable
+//! sections = ["credentials", "network", "remote", "host_keys"]
+//! theme = "/etc/network-status/theme.toml"  # colours, texts and logo, see `theme`
+//! ```
+
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use crate::layout;
+use crate::rotation::Rotation;
+use crate::theme::Theme;
+
+/// Used when neither `--config` nor `$NETWORK_STATUS_CONFIG` is given.
+/// It is fine for this file not to exist.
+pub const DEFAULT_CONFIG_PATH: &str = "/etc/network-status.toml";
+pub const CONFIG_ENV_VAR: &str = "NETWORK_STATUS_CONFIG";
+
+/// Ways of drawing the graphical display
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Backend {
+    /// A dumb buffer on a DRM/KMS device
+    Drm,
+    /// The legacy framebuffer device
+    Fbdev,
+}
+
+/// Which text sections are shown below the QR code
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Sections {
+    pub credentials: bool,
+    pub network: bool,
+    pub remote: bool,
+    pub host_keys: bool,
+}
+
+impl Default for Sections {
+    fn default() -> Self {
+        Sections {
+            credentials: true,
+            network: true,
+            remote: true,
+            host_keys: true,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Config {
+    pub root_password_file: PathBuf,
+    pub onion_hostname_file: PathBuf,
+    pub hostname_file: PathBuf,
+    pub ssh_dir: PathBuf,
+    pub sshd_config_file: PathBuf,
+    /// Only this framebuffer device instead of all of them
+    pub framebuffer: Option<PathBuf>,
+    /// Only this DRM device instead of all of them
+    pub drm_device: Option<PathBuf>,
+    pub backends: Vec<Backend>,
+    pub layout: layout::Mode,
+    /// `None` to rotate like the console
+    pub rotate: Option<Rotation>,
+    /// Text size in points, `None` to derive it from the resolution
+    pub font_size: Option<u32>,
+    /// Fonts for characters the built-in one doesn't have
+    pub fonts: Vec<PathBuf>,
+    pub poll_interval: Duration,
+    pub sections: Sections,
+    pub theme: Theme,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            root_password_file: PathBuf::from("/var/shared/root-password"),
+            onion_hostname_file: PathBuf::from("/var/lib/tor/onion/hidden-ssh/hostname"),
+            hostname_file: PathBuf::from("/etc/hostname"),
+            ssh_dir: PathBuf::from("/etc/ssh"),
+            sshd_config_file: PathBuf::from("/etc/ssh/sshd_config"),
+            framebuffer: None,
+            drm_device: None,
+            backends: vec![Backend::Drm, Backend::Fbdev],
+            layout: layout::Mode::Auto,
+            rotate: None,
+            font_size: None,
+            fonts: Vec::new(),
+            poll_interval: Duration::from_secs(2),
+            sections: Sections::default(),
+            theme: Theme::default(),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum ConfigError {
+    Io(PathBuf, io::Error),
+    Parse { path: PathBuf, line: usize, message: String },
+    /// The file as a whole is inconsistent
+    Invalid(PathBuf, String),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::Io(path, e) => write!(f, "{}: {}", path.display(), e),
+            ConfigError::Parse { path, line, message } => {
+                write!(f, "{}:{}: {}", path.display(), line, message)
+            }
+            ConfigError::Invalid(path, message) => write!(f, "{}: {}", path.display(), message),
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+impl From<ConfigError> for io::Error {
+    fn from(e: ConfigError) -> Self {
+        io::Error::new(io::ErrorKind::InvalidData, e)
+    }
+}
+
+pub enum Value {
+    String(String),
+    Integer(u64),
+    Array(Vec<String>),
+}
+
+impl Config {
+    /// Load the configuration from `explicit_path`, `$NETWORK_STATUS_CONFIG` or
+    /// the default location, in that order
+    ///
+    /// A missing file is only an error if its path was given explicitly.
+    pub fn load(explicit_path: Option<&Path>) -> Result<Self, ConfigError> {
+        let env_path = std::env::var_os(CONFIG_ENV_VAR).filter(|p| !p.is_empty());
+        let (path, required) = match (explicit_path, env_path) {
+            (Some(path), _) => (path.to_path_buf(), true),
+            (None, Some(path)) => (PathBuf::from(path), true),
+            (None, None) => (PathBuf::from(DEFAULT_CONFIG_PATH), false),
+        };
+
+        match std::fs::read_to_string(&path) {
+            Ok(contents) => Config::parse(&path, &contents),
+            Err(e) if e.kind() == io::ErrorKind::NotFound && !required => Ok(Config::default()),
+            Err(e) => Err(ConfigError::Io(path, e)),
+        }
+    }
+
+    fn parse(path: &Path, contents: &str) -> Result<Self, ConfigError> {
+        let mut config = Config::default();
+
+        parse_lines(path, contents, |key, value| {
+            match (key, value) {
+                ("root_password_file", Value::String(s)) => config.root_password_file = s.into(),
+                ("onion_hostname_file", Value::String(s)) => config.onion_hostname_file = s.into(),
+                ("hostname_file", Value::String(s)) => config.hostname_file = s.into(),
+                ("ssh_dir", Value::String(s)) => config.ssh_dir = s.into(),
+                ("sshd_config_file", Value::String(s)) => config.sshd_config_file = s.into(),
+                ("framebuffer", Value::String(s)) => config.framebuffer = Some(s.into()),
+                ("drm_device", Value::String(s)) => config.drm_device = Some(s.into()),
+                ("layout", Value::String(s)) => {
+                    config.layout = layout::Mode::from_name(&s)
+                        .ok_or_else(|| format!("unknown layout `{}`, expected {}", s, layout::Mode::NAMES))?;
+                }
+                ("rotate", Value::Integer(degrees)) => {
+                    config.rotate = Some(Rotation::from_degrees(&degrees.to_string()).ok_or_else(|| {
+                        format!("unknown rotation `{}`, expected {}", degrees, Rotation::NAMES)
+                    })?);
+                }
+                ("theme", Value::String(s)) => {
+                    config.theme = Theme::load(Path::new(&s)).map_err(|e| format!("theme: {}", e))?;
+                }
+                ("poll_interval", Value::Integer(0)) => {
+                    return Err("poll_interval must be at least 1 second".to_string());
+                }
+                ("poll_interval", Value::Integer(secs)) => {
+                    config.poll_interval = Duration::from_secs(secs);
+                }
+                ("font_size", Value::Integer(0)) => {
+                    return Err("font_size must be at least 1 point".to_string());
+                }
+                ("font_size", Value::Integer(points)) => {
+                    config.font_size = Some(points.min(u32::MAX as u64) as u32);
+                }
+                ("fonts", Value::Array(paths)) => {
+                    config.fonts = paths.into_iter().map(PathBuf::from).collect();
+                }
+                ("sections", Value::Array(names)) => config.sections = parse_sections(&names)?,
+                ("backends", Value::Array(names)) => config.backends = parse_backends(&names)?,
+                (
+                    "root_password_file" | "onion_hostname_file" | "hostname_file" | "ssh_dir"
+                    | "sshd_config_file" | "framebuffer" | "drm_device" | "layout" | "theme",
+                    _,
+                ) => return Err(format!("`{}` must be a string", key)),
+                ("poll_interval" | "font_size" | "rotate", _) => {
+                    return Err(format!("`{}` must be an integer", key));
+                }
+                ("sections" | "backends" | "fonts", _) => {
+                    return Err(format!("`{}` must be an array of strings", key));
+                }
+                _ => return Err(format!("unknown key `{}`", key)),
+            }
+            Ok(())
+        })?;
+
+        Ok(config)
+    }
+}
+
+/// Call `set` with every `key = value` line of `contents`, its errors are
+/// reported with the line they are about
+pub fn parse_lines(
+    path: &Path,
+    contents: &str,
+    mut set: impl FnMut(&str, Value) -> Result<(), String>,
+) -> Result<(), ConfigError> {
+    for (index, raw_line) in contents.lines().enumerate() {
+        let error = |message: String| ConfigError::Parse {
+            path: path.to_path_buf(),
+            line: index + 1,
+            message,
+        };
+
+        let line = strip_comment(raw_line).trim();
+        if line.is_empty() {
+            continue;
+        }
+
+        let (key, value) = line
+            .split_once('=')
+            .ok_or_else(|| error(format!("expected `key = value`, got `{}`", line)))?;
+        let value = parse_value(value.trim()).map_err(&error)?;
+        set(key.trim(), value).map_err(&error)?;
+    }
+    Ok(())
+}
+
+fn parse_sections(names: &[String]) -> Result<Sections, String> {
+    let mut sections = Sections {
+        credentials: false,
+        network: false,
+        remote: false,
+        host_keys: false,
+    };
+    for name in names {
+        match name.as_str() {
+            "credentials" => sections.credentials = true,
+            "network" => sections.network = true,
+            "remote" => sections.remote = true,
+            "host_keys" => sections.host_keys = true,
+            other => {
+                return Err(format!(
+                    "unknown section `{}`, expected one of credentials, network, remote, host_keys",
+                    other
+                ))
+            }
+        }
+    }
+    Ok(sections)
+}
+
+fn parse_backends(names: &[String]) -> Result<Vec<Backend>, String> {
+    let mut backends = Vec::new();
+    for name in names {
+        let backend = match name.as_str() {
+            "drm" => Backend::Drm,
+            "fbdev" => Backend::Fbdev,
+            other => return Err(format!("unknown backend `{}`, expected drm or fbdev", other)),
+        };
+        if backends.contains(&backend) {
+            return Err(format!("backend `{}` listed twice", name));
+        }
+        backends.push(backend);
+    }
+    Ok(backends)
+}
+
+/// Remove a trailing `# comment`, ignoring `#` inside quoted strings
+fn strip_comment(line: &str) -> &str {
+    let mut in_string = false;
+    let mut escaped = false;
+    for (i, ch) in line.char_indices() {
+        match ch {
+            _ if escaped => escaped = false,
+            '\\' if in_string => escaped = true,
+            '"' => in_string = !in_string,
+            '#' if !in_string => return &line[..i],
+            _ => {}
+        }
+    }
+    line
+}
+
+fn parse_value(value: &str) -> Result<Value, String> {
+    if let Some(inner) = value.strip_prefix('[') {
+        let inner = inner
+            .strip_suffix(']')
+            .ok_or_else(|| format!("unterminated array `{}`", value))?;
+        let items = split_array(inner)?
+            .into_iter()
+            .map(|item| parse_string(item.trim()))
+            .collect::<Result<_, _>>()?;
+        return Ok(Value::Array(items));
+    }
+    if value.starts_with('"') {
+        return parse_string(value).map(Value::String);
+    }
+    value
+        .parse()
+        .map(Value::Integer)
+        .map_err(|_| format!("invalid value `{}`", value))
+}
+
+/// Split the inside of an array on commas outside of strings
+fn split_array(inner: &str) -> Result<Vec<&str>, String> {
+    let mut items = Vec::new();
+    let mut start = 0;
+    let mut in_string = false;
+    let mut escaped = false;
+    for (i, ch) in inner.char_indices() {
+        match ch {
+            _ if escaped => escaped = false,
+            '\\' if in_string => escaped = true,
+            '"' => in_string = !in_string,
+            ',' if !in_string => {
+                items.push(&inner[start..i]);
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    if in_string {
+        return Err("unterminated string in array".to_string());
+    }
+    // Allow a trailing comma
+    if !inner[start..].trim().is_empty() {
+        items.push(&inner[start..]);
+    }
+    Ok(items)
+}
+
+fn parse_string(value: &str) -> Result<String, String> {
+    let inner = value
+        .strip_prefix('"')
+        .and_then(|v| v.strip_suffix('"'))
+        .filter(|_| value.len() >= 2)
+        .ok_or_else(|| format!("expected a quoted string, got `{}`", value))?;
+
+    let mut result = String::with_capacity(inner.len());
+    let mut chars = inner.chars();
+    while let Some(ch) = chars.next() {
+        if ch != '\\' {
+            result.push(ch);
+            continue;
+        }
+        match chars.next() {
+            Some('\\') => result.push('\\'),
+            Some('"') => result.push('"'),
+            Some('n') => result.push('\n'),
+            Some('t') => result.push('\t'),
+            other => return Err(format!("unsupported escape `\\{}`", other.unwrap_or(' '))),
+        }
+    }
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(contents: &str) -> Result<Config, String> {
+        Config::parse(Path::new("test.toml"), contents).map_err(|e| e.to_string())
+    }
+
+    #[test]
+    fn strings_and_comments() {
+        let config = parse(
+            "# paths\n\
+             \n\
+             ssh_dir = \"/etc/ssh # not a comment\"  # but this is\n\
+             hostname_file = \"/a \\\"quoted\\\" \\\\ name\\n\\t\"\n\
+             \x20 sshd_config_file=\"/etc/sshd#config\"\n",
+        )
+        .unwrap();
+        assert_eq!(config.ssh_dir, Path::new("/etc/ssh # not a comment"));
+        assert_eq!(config.hostname_file, Path::new("/a \"quoted\" \\ name\n\t"));
+        assert_eq!(config.sshd_config_file, Path::new("/etc/sshd#config"));
+        // Everything else keeps its default
+        assert_eq!(config.root_password_file, Config::default().root_password_file);
+
+        assert_eq!(parse("ssh_dir = \"a\\x\"").unwrap_err(), "test.toml:1: unsupported escape `\\x`");
+        assert_eq!(
+            parse("ssh_dir = \"/etc/ssh").unwrap_err(),
+            "test.toml:1: expected a quoted string, got `\"/etc/ssh`"
+        );
+        assert_eq!(parse("ssh_dir = \"").unwrap_err(), "test.toml:1: expected a quoted string, got `\"`");
+    }
+
+    #[test]
+    fn arrays() {
+        let config = parse(
+            "fonts = [\"/a\", \"/b,c\" , \"/d \\\"e]\\\"\",]\n\
+             sections = [\"network\", \"host_keys\",]  # trailing comma\n\
+             backends = [\"fbdev\"]\n",
+        )
+        .unwrap();
+        assert_eq!(config.fonts, [Path::new("/a"), Path::new("/b,c"), Path::new("/d \"e]\"")]);
+        assert_eq!(
+            config.sections,
+            Sections { credentials: false, network: true, remote: false, host_keys: true }
+        );
+        assert_eq!(config.backends, [Backend::Fbdev]);
+        assert!(parse("fonts = []").unwrap().fonts.is_empty());
+
+        assert_eq!(parse("fonts = [\"/a\"").unwrap_err(), "test.toml:1: unterminated array `[\"/a\"`");
+        assert_eq!(parse("fonts = [\"/a]").unwrap_err(), "test.toml:1: unterminated string in array");
+        assert_eq!(parse("fonts = [1]").unwrap_err(), "test.toml:1: expected a quoted string, got `1`");
+        assert_eq!(
+            parse("backends = [\"drm\", \"drm\"]").unwrap_err(),
+            "test.toml:1: backend `drm` listed twice"
+        );
+        assert_eq!(
+            parse("sections = [\"qr\"]").unwrap_err(),
+            "test.toml:1: unknown section `qr`, expected one of credentials, network, remote, host_keys"
+        );
+    }
+
+    #[test]
+    fn errors_name_the_line() {
+        let error = |contents: &str| parse(contents).unwrap_err();
+        assert_eq!(error("\n# comment\nfont = \"x\""), "test.toml:3: unknown key `font`");
+        assert_eq!(error("ssh_dir = 1"), "test.toml:1: `ssh_dir` must be a string");
+        assert_eq!(error("layout = [\"auto\"]"), "test.toml:1: `layout` must be a string");
+        assert_eq!(error("ssh_dir = \"/\"\npoll_interval = \"2\""), "test.toml:2: `poll_interval` must be an integer");
+        assert_eq!(error("rotate = \"90\""), "test.toml:1: `rotate` must be an integer");
+        assert_eq!(error("fonts = \"/a\""), "test.toml:1: `fonts` must be an array of strings");
+        assert_eq!(error("poll_interval = -1"), "test.toml:1: invalid value `-1`");
+        assert_eq!(error("ssh_dir"), "test.toml:1: expected `key = value`, got `ssh_dir`");
+        assert_eq!(error("rotate = 45"), "test.toml:1: unknown rotation `45`, expected 0, 90, 180 or 270");
+    }
+
+    #[test]
+    fn integers() {
+        let config = parse("poll_interval = 5\nfont_size = 14\nrotate = 270").unwrap();
+        assert_eq!(config.poll_interval, Duration::from_secs(5));
+        assert_eq!(config.font_size, Some(14));
+        assert_eq!(config.rotate, Some(Rotation::CounterClockwise));
+
+        assert_eq!(
+            parse("poll_interval = 0").unwrap_err(),
+            "test.toml:1: poll_interval must be at least 1 second"
+        );
+        assert_eq!(parse("font_size = 0").unwrap_err(), "test.toml:1: font_size must be at least 1 point");
+    }
+
+    #[test]
+    fn config_path_from_flag_or_environment() {
+        let dir = std::env::temp_dir().join(format!("network-status-config-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let from_env = dir.join("env.toml");
+        let from_flag = dir.join("flag.toml");
+        std::fs::write(&from_env, "hostname_file = \"/env\"").unwrap();
+        std::fs::write(&from_flag, "hostname_file = \"/flag\"").unwrap();
+        let missing = dir.join("missing.toml");
+
+        // The only test touching the variable
+        std::env::set_var(CONFIG_ENV_VAR, &from_env);
+        assert_eq!(Config::load(None).unwrap().hostname_file, Path::new("/env"));
+        assert_eq!(Config::load(Some(&from_flag)).unwrap().hostname_file, Path::new("/flag"));
+        // Given explicitly, so it has to exist
+        assert!(matches!(Config::load(Some(&missing)), Err(ConfigError::Io(path, _)) if path == missing));
+        std::env::set_var(CONFIG_ENV_VAR, &missing);
+        assert!(matches!(Config::load(None), Err(ConfigError::Io(path, _)) if path == missing));
+        // Empty is the same as unset
+        std::env::set_var(CONFIG_ENV_VAR, "");
+        assert_eq!(Config::load(Some(&from_flag)).unwrap().hostname_file, Path::new("/flag"));
+        std::env::remove_var(CONFIG_ENV_VAR);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,121 @@
+//! What changed between two frames
+//!
+//! Frames are drawn off screen and only the pixels that differ from the
+//! previous frame are copied to the device, so an update never shows a
+//! half-drawn screen and a ticking line of text costs a few rows instead of
+//! the whole panel.
+
+use crate::layout::Rect;
+use crate::FramebufferConfig;
+
+/// Rectangles covering every pixel where `old` and `new` differ
+///
+/// Runs of changed rows become one rectangle, as wide as the changes in all
+/// of them. An empty `old` means nothing is known about the screen, so all of
+/// it is damaged.
+pub fn diff(old: &[u8], new: &[u8], fb_config: &FramebufferConfig) -> Vec<Rect> {
+    let screen = Rect { x: 0, y: 0, width: fb_config.width, height: fb_config.height };
+    if old.len() != new.len() {
+        return vec![screen];
+    }
+
+    let bytes_per_pixel = fb_config.format.bytes_per_pixel;
+    let visible_bytes = fb_config.width * bytes_per_pixel;
+    let mut damage: Vec<Rect> = Vec::new();
+    // The rectangle grown by the previous row, if it changed
+    let mut open = false;
+    for y in 0..fb_config.height {
+        let old_row = &old[fb_config.offset(0, y)..][..visible_bytes];
+        let new_row = &new[fb_config.offset(0, y)..][..visible_bytes];
+        let Some(first) = old_row.iter().zip(new_row).position(|(a, b)| a != b) else {
+            open = false;
+            continue;
+        };
+        let last = visible_bytes - old_row.iter().zip(new_row).rev().position(|(a, b)| a != b).unwrap_or(0);
+        let (left, right) = (first / bytes_per_pixel, last.div_ceil(bytes_per_pixel));
+
+        match damage.last_mut() {
+            Some(rect) if open => {
+                let right = right.max(rect.right());
+                rect.x = rect.x.min(left);
+                rect.width = right - rect.x;
+                rect.height += 1;
+            }
+            _ => damage.push(Rect { x: left, y, width: right - left, height: 1 }),
+        }
+        open = true;
+    }
+    damage
+}
+
+/// Copy the `damage` rectangles of `frame` into `screen`
+pub fn copy(frame: &[u8], screen: &mut [u8], damage: &[Rect], fb_config: &FramebufferConfig) {
+    for rect in damage {
+        for y in rect.y..rect.bottom() {
+            let span = fb_config.offset(rect.x, y)..fb_config.offset(rect.right(), y);
+            screen[span.clone()].copy_from_slice(&frame[span]);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pixel::{Channel, PixelFormat};
+    use crate::rotation::Rotation;
+
+    /// 8x6 pixels of one byte each, lines padded to 10
+    fn config() -> FramebufferConfig {
+        FramebufferConfig {
+            width: 8,
+            height: 6,
+            stride: 10,
+            dpi: None,
+            format: PixelFormat {
+                bytes_per_pixel: 1,
+                red: Channel::new(0, 8),
+                green: Channel::new(0, 8),
+                blue: Channel::new(0, 8),
+                alpha: Channel::new(0, 0),
+            },
+            rotation: Rotation::Normal,
+        }
+    }
+
+    fn rect(x: usize, y: usize, width: usize, height: usize) -> Rect {
+        Rect { x, y, width, height }
+    }
+
+    #[test]
+    fn unchanged_frames_have_no_damage() {
+        let frame = vec![7; 60];
+        assert!(diff(&frame, &frame, &config()).is_empty());
+        // Nothing shown yet
+        assert_eq!(diff(&[], &frame, &config()), [rect(0, 0, 8, 6)]);
+    }
+
+    #[test]
+    fn changed_rows_are_merged() {
+        let old = vec![0; 60];
+        let mut new = old.clone();
+        new[10 + 3] = 1;
+        new[20 + 1] = 1;
+        new[20 + 5] = 1;
+        new[50 + 7] = 1;
+        // Line padding isn't on screen
+        new[40 + 9] = 1;
+        assert_eq!(diff(&old, &new, &config()), [rect(1, 1, 5, 2), rect(7, 5, 1, 1)]);
+    }
+
+    #[test]
+    fn copy_touches_only_the_damage() {
+        let frame: Vec<u8> = (0..60).collect();
+        let mut screen = vec![0xFF; 60];
+        let damage = diff(&screen, &frame, &config());
+        copy(&frame, &mut screen, &damage, &config());
+        for y in 0..6 {
+            assert_eq!(screen[y * 10..][..8], frame[y * 10..][..8]);
+            assert_eq!(screen[y * 10 + 8..][..2], [0xFF, 0xFF]);
+        }
+    }
+}
@@ -0,0 +1,674 @@
+//! DRM/KMS output through a dumb buffer
+//!
+//! Used on kernels built without fbdev emulation (`CONFIG_FB_DEVICE`), where
+//! `/dev/fb0` never appears. We pick the first connected connector, allocate a
+//! single XRGB8888 dumb buffer in its preferred mode and point a CRTC at it.
+//! The previous CRTC configuration is restored when the output is dropped.
+//!
+//! Talks to the kernel with the legacy modesetting ioctls, so we don't need
+//! libdrm.
+
+use std::fs::{File, OpenOptions};
+use std::io;
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+use crate::layout::Rect;
+use crate::pixel::{Channel, PixelFormat};
+use crate::rotation::Rotation;
+use crate::{FramebufferConfig, FramebufferMap};
+
+pub const DRI_DIR: &str = "/dev/dri";
+
+// Ioctl numbers from <drm/drm.h>, all _IOWR('d', nr, struct)
+const DRM_IOCTL_GET_CAP: u32 = 0x0c;
+const DRM_IOCTL_SET_MASTER: u32 = 0x1e;
+const DRM_IOCTL_DROP_MASTER: u32 = 0x1f;
+const DRM_IOCTL_MODE_GETRESOURCES: u32 = 0xa0;
+const DRM_IOCTL_MODE_GETCRTC: u32 = 0xa1;
+const DRM_IOCTL_MODE_SETCRTC: u32 = 0xa2;
+const DRM_IOCTL_MODE_GETENCODER: u32 = 0xa6;
+const DRM_IOCTL_MODE_GETCONNECTOR: u32 = 0xa7;
+const DRM_IOCTL_MODE_ADDFB: u32 = 0xae;
+const DRM_IOCTL_MODE_RMFB: u32 = 0xaf;
+const DRM_IOCTL_MODE_DIRTYFB: u32 = 0xb1;
+const DRM_IOCTL_MODE_CREATE_DUMB: u32 = 0xb2;
+const DRM_IOCTL_MODE_MAP_DUMB: u32 = 0xb3;
+const DRM_IOCTL_MODE_DESTROY_DUMB: u32 = 0xb4;
+
+const DRM_CAP_DUMB_BUFFER: u64 = 0x1;
+const DRM_MODE_CONNECTED: u32 = 1;
+const DRM_MODE_TYPE_PREFERRED: u32 = 1 << 3;
+
+/// `_IOWR('d', nr, T)` from <asm-generic/ioctl.h>
+const fn iowr(nr: u32, size: usize) -> u32 {
+    (3 << 30) | ((size as u32) << 16) | ((b'd' as u32) << 8) | nr
+}
+
+/// `_IO('d', nr)`, for ioctls without an argument
+const fn io(nr: u32) -> u32 {
+    ((b'd' as u32) << 8) | nr
+}
+
+#[repr(C)]
+#[derive(Default)]
+struct DrmGetCap {
+    capability: u64,
+    value: u64,
+}
+
+#[repr(C)]
+#[derive(Default)]
+struct DrmModeCardRes {
+    fb_id_ptr: u64,
+    crtc_id_ptr: u64,
+    connector_id_ptr: u64,
+    encoder_id_ptr: u64,
+    count_fbs: u32,
+    count_crtcs: u32,
+    count_connectors: u32,
+    count_encoders: u32,
+    min_width: u32,
+    max_width: u32,
+    min_height: u32,
+    max_height: u32,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Default)]
+struct DrmModeModeinfo {
+    clock: u32,
+    hdisplay: u16,
+    hsync_start: u16,
+    hsync_end: u16,
+    htotal: u16,
+    hskew: u16,
+    vdisplay: u16,
+    vsync_start: u16,
+    vsync_end: u16,
+    vtotal: u16,
+    vscan: u16,
+    vrefresh: u32,
+    flags: u32,
+    type_: u32,
+    name: [u8; 32],
+}
+
+#[repr(C)]
+#[derive(Default)]
+struct DrmModeGetConnector {
+    encoders_ptr: u64,
+    modes_ptr: u64,
+    props_ptr: u64,
+    prop_values_ptr: u64,
+    count_modes: u32,
+    count_props: u32,
+    count_encoders: u32,
+    encoder_id: u32,
+    connector_id: u32,
+    connector_type: u32,
+    connector_type_id: u32,
+    connection: u32,
+    mm_width: u32,
+    mm_height: u32,
+    subpixel: u32,
+    pad: u32,
+}
+
+#[repr(C)]
+#[derive(Default)]
+struct DrmModeGetEncoder {
+    encoder_id: u32,
+    encoder_type: u32,
+    crtc_id: u32,
+    possible_crtcs: u32,
+    possible_clones: u32,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Default)]
+struct DrmModeCrtc {
+    set_connectors_ptr: u64,
+    count_connectors: u32,
+    crtc_id: u32,
+    fb_id: u32,
+    x: u32,
+    y: u32,
+    gamma_size: u32,
+    mode_valid: u32,
+    mode: DrmModeModeinfo,
+}
+
+#[repr(C)]
+#[derive(Default)]
+struct DrmModeCreateDumb {
+    height: u32,
+    width: u32,
+    bpp: u32,
+    flags: u32,
+    handle: u32,
+    pitch: u32,
+    size: u64,
+}
+
+#[repr(C)]
+#[derive(Default)]
+struct DrmModeMapDumb {
+    handle: u32,
+    pad: u32,
+    offset: u64,
+}
+
+#[repr(C)]
+#[derive(Default)]
+struct DrmModeDestroyDumb {
+    handle: u32,
+}
+
+#[repr(C)]
+#[derive(Default)]
+struct DrmModeFbCmd {
+    fb_id: u32,
+    width: u32,
+    height: u32,
+    pitch: u32,
+    bpp: u32,
+    depth: u32,
+    handle: u32,
+}
+
+#[repr(C)]
+#[derive(Default)]
+struct DrmModeFbDirtyCmd {
+    fb_id: u32,
+    flags: u32,
+    color: u32,
+    num_clips: u32,
+    clips_ptr: u64,
+}
+
+#[repr(C)]
+struct DrmClipRect {
+    x1: u16,
+    y1: u16,
+    x2: u16,
+    y2: u16,
+}
+
+/// Issue a DRM ioctl, retrying like libdrm does when interrupted
+fn ioctl<T>(fd: RawFd, nr: u32, arg: &mut T) -> io::Result<()> {
+    let request = iowr(nr, std::mem::size_of::<T>());
+    loop {
+        let ret = unsafe { libc::ioctl(fd, request as _, arg as *mut T) };
+        if ret == 0 {
+            return Ok(());
+        }
+        let err = io::Error::last_os_error();
+        if !matches!(err.raw_os_error(), Some(libc::EINTR | libc::EAGAIN)) {
+            return Err(err);
+        }
+    }
+}
+
+struct Connector {
+    connected: bool,
+    encoder_id: u32,
+    encoders: Vec<u32>,
+    modes: Vec<DrmModeModeinfo>,
+    /// Physical width of the display, 0 if unknown
+    mm_width: u32,
+}
+
+/// CRTC and connector ids of the device
+fn get_resources(fd: RawFd) -> io::Result<(Vec<u32>, Vec<u32>)> {
+    loop {
+        let mut res = DrmModeCardRes::default();
+        ioctl(fd, DRM_IOCTL_MODE_GETRESOURCES, &mut res)?;
+
+        let mut crtcs = vec![0u32; res.count_crtcs as usize];
+        let mut connectors = vec![0u32; res.count_connectors as usize];
+        let mut fetched = DrmModeCardRes {
+            crtc_id_ptr: crtcs.as_mut_ptr() as u64,
+            connector_id_ptr: connectors.as_mut_ptr() as u64,
+            count_crtcs: res.count_crtcs,
+            count_connectors: res.count_connectors,
+            ..Default::default()
+        };
+        ioctl(fd, DRM_IOCTL_MODE_GETRESOURCES, &mut fetched)?;
+
+        // Hotplug between the two calls, try again
+        if fetched.count_crtcs > res.count_crtcs || fetched.count_connectors > res.count_connectors {
+            continue;
+        }
+        crtcs.truncate(fetched.count_crtcs as usize);
+        connectors.truncate(fetched.count_connectors as usize);
+        return Ok((crtcs, connectors));
+    }
+}
+
+/// Probe a connector, which also refreshes its list of modes
+fn get_connector(fd: RawFd, id: u32) -> io::Result<Connector> {
+    loop {
+        let mut probe = DrmModeGetConnector { connector_id: id, ..Default::default() };
+        ioctl(fd, DRM_IOCTL_MODE_GETCONNECTOR, &mut probe)?;
+
+        let mut encoders = vec![0u32; probe.count_encoders as usize];
+        let mut modes = vec![DrmModeModeinfo::default(); probe.count_modes as usize];
+        let mut fetched = DrmModeGetConnector {
+            connector_id: id,
+            encoders_ptr: encoders.as_mut_ptr() as u64,
+            modes_ptr: modes.as_mut_ptr() as u64,
+            count_encoders: probe.count_encoders,
+            count_modes: probe.count_modes,
+            ..Default::default()
+        };
+        ioctl(fd, DRM_IOCTL_MODE_GETCONNECTOR, &mut fetched)?;
+
+        if fetched.count_encoders > probe.count_encoders || fetched.count_modes > probe.count_modes {
+            continue;
+        }
+        encoders.truncate(fetched.count_encoders as usize);
+        modes.truncate(fetched.count_modes as usize);
+        return Ok(Connector {
+            connected: fetched.connection == DRM_MODE_CONNECTED,
+            encoder_id: fetched.encoder_id,
+            encoders,
+            modes,
+            mm_width: fetched.mm_width,
+        });
+    }
+}
+
+/// Whether a display is connected, as last detected by the kernel
+///
+/// Asking for at least one mode keeps the kernel from probing the connector
+/// again, which can take a while (e.g. reading the EDID).
+fn get_connector_status(fd: RawFd, id: u32) -> io::Result<bool> {
+    let mut mode = DrmModeModeinfo::default();
+    let mut connector = DrmModeGetConnector {
+        connector_id: id,
+        modes_ptr: &mut mode as *mut DrmModeModeinfo as u64,
+        count_modes: 1,
+        ..Default::default()
+    };
+    ioctl(fd, DRM_IOCTL_MODE_GETCONNECTOR, &mut connector)?;
+    Ok(connector.connection == DRM_MODE_CONNECTED)
+}
+
+fn get_encoder(fd: RawFd, id: u32) -> io::Result<DrmModeGetEncoder> {
+    let mut encoder = DrmModeGetEncoder { encoder_id: id, ..Default::default() };
+    ioctl(fd, DRM_IOCTL_MODE_GETENCODER, &mut encoder)?;
+    Ok(encoder)
+}
+
+/// The CRTC currently driving `connector`, or the first free one that can
+fn find_crtc(fd: RawFd, connector: &Connector, crtcs: &[u32], busy: &[u32]) -> Option<u32> {
+    if connector.encoder_id != 0 {
+        if let Ok(encoder) = get_encoder(fd, connector.encoder_id) {
+            if encoder.crtc_id != 0 && !busy.contains(&encoder.crtc_id) {
+                return Some(encoder.crtc_id);
+            }
+        }
+    }
+    connector.encoders.iter().find_map(|&id| {
+        let encoder = get_encoder(fd, id).ok()?;
+        crtcs
+            .iter()
+            .enumerate()
+            .find(|&(i, crtc)| encoder.possible_crtcs & (1 << i) != 0 && !busy.contains(crtc))
+            .map(|(_, &crtc)| crtc)
+    })
+}
+
+/// Dumb buffer handle, destroyed on drop
+struct DumbBuffer {
+    fd: RawFd,
+    handle: u32,
+}
+
+impl Drop for DumbBuffer {
+    fn drop(&mut self) {
+        let _ = ioctl(self.fd, DRM_IOCTL_MODE_DESTROY_DUMB, &mut DrmModeDestroyDumb { handle: self.handle });
+    }
+}
+
+/// Framebuffer object wrapping the dumb buffer, removed on drop
+struct Framebuffer {
+    fd: RawFd,
+    id: u32,
+}
+
+impl Drop for Framebuffer {
+    fn drop(&mut self) {
+        let _ = ioctl(self.fd, DRM_IOCTL_MODE_RMFB, &mut self.id);
+    }
+}
+
+/// What the CRTC showed before we took it over, put back on drop
+struct SavedCrtc {
+    fd: RawFd,
+    connector_id: u32,
+    crtc: DrmModeCrtc,
+}
+
+impl Drop for SavedCrtc {
+    fn drop(&mut self) {
+        // Nothing to restore if it was off, fbcon takes over again once we close the device
+        if self.crtc.mode_valid == 0 || self.crtc.fb_id == 0 {
+            return;
+        }
+        let mut connector_id = self.connector_id;
+        self.crtc.set_connectors_ptr = &mut connector_id as *mut u32 as u64;
+        self.crtc.count_connectors = 1;
+        let _ = ioctl(self.fd, DRM_IOCTL_MODE_SETCRTC, &mut self.crtc);
+    }
+}
+
+/// An open DRM device, shared by the outputs on its connectors
+pub struct Card {
+    pub path: PathBuf,
+    file: Rc<File>,
+    probed: bool,
+    master: bool,
+}
+
+impl Card {
+    pub fn open(path: &Path) -> io::Result<Self> {
+        let file = OpenOptions::new().read(true).write(true).open(path)?;
+        let fd = file.as_raw_fd();
+
+        let mut cap = DrmGetCap { capability: DRM_CAP_DUMB_BUFFER, value: 0 };
+        if ioctl(fd, DRM_IOCTL_GET_CAP, &mut cap).is_err() || cap.value == 0 {
+            return Err(io::Error::new(io::ErrorKind::Unsupported, "no dumb buffer support"));
+        }
+
+        let mut card = Card {
+            path: path.to_path_buf(),
+            file: Rc::new(file),
+            probed: false,
+            master: false,
+        };
+        card.set_master(true);
+        Ok(card)
+    }
+
+    /// Take DRM master to draw, or give it up for others (e.g. a compositor
+    /// on another VT) while we have no output on the card
+    pub fn set_master(&mut self, master: bool) {
+        if master == self.master {
+            return;
+        }
+        // Usually we already are master as the first to open the device,
+        // this fails harmlessly if so and SETCRTC reports it if someone else is
+        let request = if master { DRM_IOCTL_SET_MASTER } else { DRM_IOCTL_DROP_MASTER };
+        unsafe {
+            libc::ioctl(self.file.as_raw_fd(), io(request) as _);
+        }
+        self.master = master;
+    }
+
+    /// Connectors with a display attached
+    ///
+    /// The first call probes every connector, later calls only return the
+    /// status the kernel keeps up to date on hotplug, which is cheap.
+    pub fn connected(&mut self) -> io::Result<Vec<u32>> {
+        let fd = self.file.as_raw_fd();
+        let (_, connectors) = get_resources(fd)?;
+        let mut connected = Vec::new();
+        for id in connectors {
+            let is_connected = if self.probed {
+                get_connector_status(fd, id)?
+            } else {
+                get_connector(fd, id)?.connected
+            };
+            if is_connected {
+                connected.push(id);
+            }
+        }
+        self.probed = true;
+        Ok(connected)
+    }
+
+    /// Show a new dumb buffer on `connector_id` in its preferred mode, using a
+    /// CRTC that isn't in `busy_crtcs`
+    pub fn output(&self, connector_id: u32, busy_crtcs: &[u32]) -> io::Result<Output> {
+        let fd = self.file.as_raw_fd();
+        let (crtcs, _) = get_resources(fd)?;
+        let connector = get_connector(fd, connector_id)?;
+        let mode = connector
+            .modes
+            .iter()
+            .find(|m| m.type_ & DRM_MODE_TYPE_PREFERRED != 0)
+            .or(connector.modes.first())
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "connector has no modes"))?;
+        let crtc_id = find_crtc(fd, &connector, &crtcs, busy_crtcs)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no free CRTC for connector"))?;
+        let (width, height) = (mode.hdisplay as u32, mode.vdisplay as u32);
+
+        let mut create = DrmModeCreateDumb { width, height, bpp: 32, ..Default::default() };
+        ioctl(fd, DRM_IOCTL_MODE_CREATE_DUMB, &mut create)?;
+        let buffer = DumbBuffer { fd, handle: create.handle };
+
+        let mut fb_cmd = DrmModeFbCmd {
+            width,
+            height,
+            pitch: create.pitch,
+            bpp: 32,
+            depth: 24,
+            handle: buffer.handle,
+            ..Default::default()
+        };
+        ioctl(fd, DRM_IOCTL_MODE_ADDFB, &mut fb_cmd)?;
+        let fb = Framebuffer { fd, id: fb_cmd.fb_id };
+
+        let mut map_dumb = DrmModeMapDumb { handle: buffer.handle, ..Default::default() };
+        ioctl(fd, DRM_IOCTL_MODE_MAP_DUMB, &mut map_dumb)?;
+        let map = unsafe { FramebufferMap::new(fd, create.size as usize, map_dumb.offset as libc::off_t)? };
+
+        let mut saved = DrmModeCrtc { crtc_id, ..Default::default() };
+        ioctl(fd, DRM_IOCTL_MODE_GETCRTC, &mut saved)?;
+
+        let mut connector_ids = connector_id;
+        let mut crtc = DrmModeCrtc {
+            set_connectors_ptr: &mut connector_ids as *mut u32 as u64,
+            count_connectors: 1,
+            crtc_id,
+            fb_id: fb.id,
+            mode_valid: 1,
+            mode: *mode,
+            ..Default::default()
+        };
+        ioctl(fd, DRM_IOCTL_MODE_SETCRTC, &mut crtc)?;
+
+        Ok(Output {
+            saved_crtc: SavedCrtc { fd, connector_id, crtc: saved },
+            map,
+            fb,
+            _buffer: buffer,
+            _file: self.file.clone(),
+            card: self.path.clone(),
+            connector_id,
+            crtc_id,
+            width: width as usize,
+            height: height as usize,
+            pitch: create.pitch as usize,
+            mm_width: connector.mm_width,
+        })
+    }
+
+    /// The device in sysfs, to recognize its fbdev emulation
+    pub fn sysfs_device(&self) -> Option<PathBuf> {
+        let name = self.path.file_name()?;
+        std::fs::canonicalize(Path::new("/sys/class/drm").join(name).join("device")).ok()
+    }
+}
+
+/// A connector showing our dumb buffer
+///
+/// Fields are dropped in declaration order: the CRTC is restored before our
+/// framebuffer goes away, and the device is closed last.
+pub struct Output {
+    saved_crtc: SavedCrtc,
+    map: FramebufferMap,
+    fb: Framebuffer,
+    _buffer: DumbBuffer,
+    _file: Rc<File>,
+    pub card: PathBuf,
+    pub connector_id: u32,
+    pub crtc_id: u32,
+    width: usize,
+    height: usize,
+    pitch: usize,
+    mm_width: u32,
+}
+
+impl Output {
+    /// XRGB8888, the only format every driver supports for dumb buffers
+    pub fn framebuffer_config(&self) -> FramebufferConfig {
+        FramebufferConfig {
+            width: self.width,
+            height: self.height,
+            stride: self.pitch,
+            dpi: crate::dpi(self.width as u32, self.mm_width),
+            format: PixelFormat {
+                bytes_per_pixel: 4,
+                red: Channel::new(16, 8),
+                green: Channel::new(8, 8),
+                blue: Channel::new(0, 8),
+                alpha: Channel::new(0, 0),
+            },
+            rotation: Rotation::Normal,
+        }
+    }
+
+    pub fn as_slice_mut(&mut self) -> &mut [u8] {
+        self.map.as_slice_mut()
+    }
+
+    /// Tell drivers that don't scan out continuously (virtio, udl, ...) to
+    /// update the `damage` rectangles of the screen
+    pub fn flush(&self, damage: &[Rect]) {
+        let clips: Vec<DrmClipRect> = damage
+            .iter()
+            .map(|rect| DrmClipRect {
+                x1: rect.x as u16,
+                y1: rect.y as u16,
+                x2: rect.right() as u16,
+                y2: rect.bottom() as u16,
+            })
+            .collect();
+        let mut dirty = DrmModeFbDirtyCmd {
+            fb_id: self.fb.id,
+            num_clips: clips.len() as u32,
+            clips_ptr: clips.as_ptr() as u64,
+            ..Default::default()
+        };
+        let _ = ioctl(self.saved_crtc.fd, DRM_IOCTL_MODE_DIRTYFB, &mut dirty);
+    }
+}
+
+/// `/dev/dri/card*`, sorted
+pub fn cards() -> io::Result<Vec<PathBuf>> {
+    let mut cards: Vec<PathBuf> = std::fs::read_dir(DRI_DIR)?
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.file_name().to_string_lossy().starts_with("card"))
+        .map(|entry| entry.path())
+        .collect();
+    cards.sort();
+    Ok(cards)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Color;
+
+    const DRM_IOCTL_VERSION: u32 = 0x00;
+
+    #[repr(C)]
+    #[derive(Default)]
+    struct DrmVersion {
+        version_major: libc::c_int,
+        version_minor: libc::c_int,
+        version_patchlevel: libc::c_int,
+        name_len: usize,
+        name: usize,
+        date_len: usize,
+        date: usize,
+        desc_len: usize,
+        desc: usize,
+    }
+
+    /// Name of the kernel driver behind `fd`, e.g. "i915" or "vkms"
+    fn driver_name(fd: RawFd) -> io::Result<String> {
+        let mut version = DrmVersion::default();
+        ioctl(fd, DRM_IOCTL_VERSION, &mut version)?;
+        let mut name = vec![0u8; version.name_len];
+        version.name = name.as_mut_ptr() as usize;
+        version.date_len = 0;
+        version.desc_len = 0;
+        ioctl(fd, DRM_IOCTL_VERSION, &mut version)?;
+        name.truncate(version.name_len);
+        Ok(String::from_utf8_lossy(&name).into_owned())
+    }
+
+    #[test]
+    fn ioctl_numbers_match_the_kernel_headers() {
+        assert_eq!(iowr(DRM_IOCTL_MODE_GETRESOURCES, std::mem::size_of::<DrmModeCardRes>()), 0xc040_64a0);
+        assert_eq!(iowr(DRM_IOCTL_MODE_GETCONNECTOR, std::mem::size_of::<DrmModeGetConnector>()), 0xc050_64a7);
+        assert_eq!(iowr(DRM_IOCTL_MODE_GETENCODER, std::mem::size_of::<DrmModeGetEncoder>()), 0xc014_64a6);
+        assert_eq!(iowr(DRM_IOCTL_MODE_SETCRTC, std::mem::size_of::<DrmModeCrtc>()), 0xc068_64a2);
+        assert_eq!(iowr(DRM_IOCTL_MODE_ADDFB, std::mem::size_of::<DrmModeFbCmd>()), 0xc01c_64ae);
+        assert_eq!(iowr(DRM_IOCTL_MODE_DIRTYFB, std::mem::size_of::<DrmModeFbDirtyCmd>()), 0xc018_64b1);
+        assert_eq!(iowr(DRM_IOCTL_MODE_CREATE_DUMB, std::mem::size_of::<DrmModeCreateDumb>()), 0xc020_64b2);
+        assert_eq!(iowr(DRM_IOCTL_MODE_MAP_DUMB, std::mem::size_of::<DrmModeMapDumb>()), 0xc010_64b3);
+        assert_eq!(io(DRM_IOCTL_SET_MASTER), 0x641e);
+        assert_eq!(io(DRM_IOCTL_DROP_MASTER), 0x641f);
+    }
+
+    /// A card driven by vkms, the virtual KMS driver (`modprobe vkms`)
+    fn find_vkms() -> Option<PathBuf> {
+        cards().ok()?.into_iter().find(|path| {
+            File::open(path)
+                .and_then(|file| driver_name(file.as_raw_fd()))
+                .is_ok_and(|name| name == "vkms")
+        })
+    }
+
+    /// Needs vkms loaded and permission to open its card, skipped otherwise
+    #[test]
+    fn vkms_modeset_and_draw() {
+        let Some(path) = find_vkms() else {
+            eprintln!("vkms not available, skipping");
+            return;
+        };
+        let mut card = match Card::open(&path) {
+            Ok(card) => card,
+            Err(e) if e.kind() == io::ErrorKind::PermissionDenied => {
+                eprintln!("{}: {}, skipping", path.display(), e);
+                return;
+            }
+            Err(e) => panic!("{}: {}", path.display(), e),
+        };
+        let connected = card.connected().unwrap();
+        assert!(!connected.is_empty(), "vkms always has a connected virtual display");
+        // Cheap status query after the first probe must agree
+        assert_eq!(card.connected().unwrap(), connected);
+
+        let mut output = card.output(connected[0], &[]).unwrap();
+        assert_eq!(output.connector_id, connected[0]);
+
+        let fb_config = output.framebuffer_config();
+        assert!(fb_config.width > 0 && fb_config.height > 0);
+        assert!(fb_config.stride >= fb_config.width * 4);
+
+        let color = Color { r: 0x12, g: 0x34, b: 0x56 };
+        let (x, y) = (fb_config.width - 1, fb_config.height - 1);
+        fb_config.put_pixel(output.as_slice_mut(), x, y, color);
+        output.flush(&[Rect { x, y, width: 1, height: 1 }]);
+
+        let offset = fb_config.offset(x, y);
+        assert_eq!(fb_config.format.read(&output.as_slice_mut()[offset..]), color);
+    }
+}
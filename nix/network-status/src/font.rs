@@ -0,0 +1,226 @@
+//! Anti-aliased text from TrueType/OpenType fonts
+//!
+//! DejaVu Sans Mono is built in and covers Latin (including the extended
+//! ranges), Greek and Cyrillic. Fonts for other scripts, e.g. CJK, can be
+//! configured as fallbacks and are asked for every character the built-in
+//! font lacks. Glyphs are rasterised to 8-bit coverage once per size and
+//! cached.
+//!
+//! Sizes are `ab_glyph` pixel scales: the distance from the lowest descender
+//! to the highest ascender, which is about 1.16 em for DejaVu.
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+use ab_glyph::{Font as _, FontArc, PxScale, ScaleFont as _};
+
+const EMBEDDED: &[u8] = include_bytes!("../DejaVuSansMono.ttf");
+
+/// Files picked up when a fallback is a directory
+const FONT_EXTENSIONS: [&str; 4] = ["ttf", "otf", "ttc", "otc"];
+
+/// Coverage bitmap of one glyph
+pub struct Glyph {
+    /// Position of the bitmap relative to the pen on the baseline
+    pub left: i32,
+    pub top: i32,
+    pub width: usize,
+    /// Row-major, `width` per row, 0 is untouched and 255 fully covered
+    pub coverage: Vec<u8>,
+}
+
+/// Rasterised glyphs by character and size
+type GlyphCache = HashMap<(char, u32), Option<Rc<Glyph>>>;
+
+pub struct Font {
+    /// The built-in font first, then the fallbacks in configured order
+    faces: Vec<FontArc>,
+    glyphs: RefCell<GlyphCache>,
+}
+
+impl Font {
+    /// Only the built-in font
+    pub fn embedded() -> Self {
+        let face = FontArc::try_from_slice(EMBEDDED).expect("embedded font is valid");
+        Font { faces: vec![face], glyphs: RefCell::new(HashMap::new()) }
+    }
+
+    /// The built-in font followed by `fallbacks`
+    ///
+    /// A fallback is a font file, or a directory that is searched recursively
+    /// for font files. Files in a directory that fail to parse are skipped,
+    /// files given directly are an error.
+    pub fn load(fallbacks: &[PathBuf]) -> io::Result<Self> {
+        let mut font = Font::embedded();
+        for path in fallbacks {
+            let error = |e: io::Error| io::Error::new(e.kind(), format!("font {}: {}", path.display(), e));
+            if path.is_dir() {
+                let mut files = Vec::new();
+                find_font_files(path, &mut files).map_err(error)?;
+                files.sort();
+                font.faces.extend(files.iter().filter_map(|file| parse(file).ok()));
+            } else {
+                font.faces.push(parse(path).map_err(error)?);
+            }
+        }
+        Ok(font)
+    }
+
+    fn scaled(&self, size: u32) -> ab_glyph::PxScaleFont<&FontArc> {
+        self.faces[0].as_scaled(PxScale::from(size as f32))
+    }
+
+    /// Horizontal advance of every character, the built-in font is monospaced
+    pub fn cell_width(&self, size: u32) -> usize {
+        let font = self.scaled(size);
+        font.h_advance(font.glyph_id('0')).ceil() as usize
+    }
+
+    /// Distance between the baselines of two rows
+    pub fn line_height(&self, size: u32) -> usize {
+        let font = self.scaled(size);
+        (font.height() + font.line_gap()).ceil() as usize
+    }
+
+    /// Distance from the top of a row to its baseline
+    pub fn ascent(&self, size: u32) -> usize {
+        self.scaled(size).ascent().round() as usize
+    }
+
+    /// Size for `points` on a screen with `dpi` pixels per inch
+    pub fn size_for_points(&self, points: u32, dpi: u32) -> u32 {
+        let face = &self.faces[0];
+        let px_per_em = points as f32 * dpi as f32 / 72.0;
+        let units_per_em = face.units_per_em().unwrap_or(2048.0);
+        (px_per_em * face.height_unscaled() / units_per_em).round() as u32
+    }
+
+    /// Whether any face has a glyph for `ch`
+    #[cfg(test)]
+    pub fn has_glyph(&self, ch: char) -> bool {
+        self.faces.iter().any(|face| face.glyph_id(ch).0 != 0)
+    }
+
+    /// The rasterised glyph for `ch`, `None` if it leaves no marks (spaces)
+    ///
+    /// Characters no face has are drawn as the built-in font's replacement
+    /// box rather than skipped.
+    pub fn glyph(&self, ch: char, size: u32) -> Option<Rc<Glyph>> {
+        self.glyphs
+            .borrow_mut()
+            .entry((ch, size))
+            .or_insert_with(|| self.rasterize(ch, size).map(Rc::new))
+            .clone()
+    }
+
+    fn rasterize(&self, ch: char, size: u32) -> Option<Glyph> {
+        let (face, id) = self
+            .faces
+            .iter()
+            .map(|face| (face, face.glyph_id(ch)))
+            .find(|(_, id)| id.0 != 0)
+            .unwrap_or((&self.faces[0], ab_glyph::GlyphId(0)));
+
+        let outline = face.outline_glyph(id.with_scale(PxScale::from(size as f32)))?;
+        let bounds = outline.px_bounds();
+        let width = bounds.width() as usize;
+        let height = bounds.height() as usize;
+        if width == 0 || height == 0 {
+            return None;
+        }
+        let mut coverage = vec![0u8; width * height];
+        outline.draw(|x, y, c| {
+            if let Some(pixel) = coverage.get_mut(y as usize * width + x as usize) {
+                *pixel = (c.clamp(0.0, 1.0) * 255.0).round() as u8;
+            }
+        });
+        Some(Glyph {
+            left: bounds.min.x as i32,
+            top: bounds.min.y as i32,
+            width,
+            coverage,
+        })
+    }
+}
+
+fn parse(path: &Path) -> io::Result<FontArc> {
+    let data = std::fs::read(path)?;
+    FontArc::try_from_vec(data).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+fn find_font_files(dir: &Path, files: &mut Vec<PathBuf>) -> io::Result<()> {
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            find_font_files(&path, files)?;
+        } else if path
+            .extension()
+            .and_then(|e| e.to_str())
+            .is_some_and(|e| FONT_EXTENSIONS.contains(&e.to_ascii_lowercase().as_str()))
+        {
+            files.push(path);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn embedded_font_covers_latin_and_cyrillic() {
+        let font = Font::embedded();
+        for ch in "AzÄßłŒžđŐ ΩЖщЁї€".chars() {
+            assert!(font.has_glyph(ch), "{:?}", ch);
+        }
+        assert!(!font.has_glyph('漢'));
+    }
+
+    #[test]
+    fn glyphs_are_anti_aliased() {
+        let font = Font::embedded();
+        let glyph = font.glyph('O', 32).unwrap();
+        assert!(glyph.coverage.contains(&255));
+        assert!(glyph.coverage.iter().any(|&c| c > 0 && c < 255));
+        // The ring sits on the baseline, above the pen position
+        let height = (glyph.coverage.len() / glyph.width) as i32;
+        assert!(glyph.top < 0 && glyph.top + height <= 1);
+        assert!(font.glyph(' ', 32).is_none());
+    }
+
+    #[test]
+    fn missing_characters_are_still_drawn() {
+        let font = Font::embedded();
+        assert!(font.glyph('漢', 32).is_some());
+    }
+
+    #[test]
+    fn metrics_scale_with_size() {
+        let font = Font::embedded();
+        assert!(font.cell_width(40) >= 2 * font.cell_width(20) - 1);
+        assert!(font.line_height(20) >= 20);
+        // 12pt at 96 DPI is 16 px per em
+        assert_eq!(font.size_for_points(12, 96), 19);
+    }
+
+    #[test]
+    fn fallbacks_are_validated() {
+        let dir = std::env::temp_dir().join(format!("network-status-font-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("nested")).unwrap();
+        let bogus = dir.join("nested/bogus.ttf");
+        std::fs::write(&bogus, b"not a font").unwrap();
+        let real = dir.join("nested/real.ttf");
+        std::fs::write(&real, EMBEDDED).unwrap();
+
+        // Unparsable files are skipped in a directory, but not when named
+        assert_eq!(Font::load(std::slice::from_ref(&dir)).unwrap().faces.len(), 2);
+        assert!(Font::load(&[bogus]).is_err());
+        assert!(Font::load(&[dir.join("missing.ttf")]).is_err());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
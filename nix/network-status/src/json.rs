@@ -0,0 +1,199 @@
+//! JSON output of the full display state for provisioning scripts
+//!
+//! One object per line:
+//!
+//! ```json
+//! {
+//!   "root_password": "correct-horse-battery",
+//!   "onion_hostname": null,
+//!   "hostname": "nixos",
+//!   "ssh_port": 22,
+//!   "interfaces": [
+//!     {
+//!       "name": "eth0",
+//!       "index": 2,
+//!       "operstate": "UP",
+//!       "mac": "52:54:00:12:34:56",
+//!       "addrs": [
+//!         { "addr": "192.0.2.2", "prefix_len": 24, "family": "inet", "scope": "global", "flags": 128 }
+//!       ]
+//!     }
+//!   ],
+//!   "host_keys": [
+//!     { "type": "ssh-ed25519", "fingerprint": "SHA256:...", "key": "ssh-ed25519 AAAA..." }
+//!   ],
+//!   "login": { ...QR code payload, see login.rs... }
+//! }
+//! ```
+//!
+//! Values that are not available yet (root password, onion hostname) are `null`.
+
+use crate::netlink::Interface;
+use crate::{DisplayState, WAITING_FOR_PASSWORD, WAITING_FOR_TOR};
+
+/// Escape `s` for use inside a JSON string literal
+fn escape_json_string(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for ch in s.chars() {
+        match ch {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            '\t' => escaped.push_str("\\t"),
+            c if (c as u32) < 0x20 => escaped.push_str(&format!("\\u{:04x}", c as u32)),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+pub fn json_string(s: &str) -> String {
+    format!("\"{}\"", escape_json_string(s))
+}
+
+/// `s` as a JSON string, or `null` if it is the given placeholder
+pub fn json_string_or_null(s: &str, placeholder: &str) -> String {
+    if s == placeholder {
+        "null".to_string()
+    } else {
+        json_string(s)
+    }
+}
+
+fn interface_json(interface: &Interface) -> String {
+    let addrs: Vec<String> = interface
+        .addrs
+        .iter()
+        .map(|addr| {
+            format!(
+                "{{\"addr\":{},\"prefix_len\":{},\"family\":\"{}\",\"scope\":{},\"flags\":{}}}",
+                json_string(&addr.addr.to_string()),
+                addr.prefix_len,
+                addr.family.as_str(),
+                json_string(&addr.scope.name()),
+                addr.flags
+            )
+        })
+        .collect();
+
+    let mac = if interface.mac.is_empty() {
+        "null".to_string()
+    } else {
+        json_string(&interface.mac_string())
+    };
+
+    format!(
+        "{{\"name\":{},\"index\":{},\"operstate\":\"{}\",\"mac\":{},\"addrs\":[{}]}}",
+        json_string(&interface.name),
+        interface.index,
+        interface.operstate.as_str(),
+        mac,
+        addrs.join(",")
+    )
+}
+
+/// Serialize the whole state as a single line of JSON
+pub fn state_json(state: &DisplayState) -> String {
+    let interfaces: Vec<String> = state.interfaces.iter().map(interface_json).collect();
+
+    let host_keys: Vec<String> = state
+        .host_keys
+        .iter()
+        .map(|key| {
+            format!(
+                "{{\"type\":{},\"fingerprint\":{},\"key\":{}}}",
+                json_string(&key.algorithm),
+                json_string(&key.fingerprint),
+                json_string(&key.public_key)
+            )
+        })
+        .collect();
+
+    let mut json = String::from("{");
+    json.push_str(&format!(
+        "\"root_password\":{},",
+        json_string_or_null(&state.root_password, WAITING_FOR_PASSWORD)
+    ));
+    json.push_str(&format!(
+        "\"onion_hostname\":{},",
+        json_string_or_null(&state.onion_hostname, WAITING_FOR_TOR)
+    ));
+    json.push_str(&format!("\"hostname\":{},", json_string(&state.hostname)));
+    json.push_str(&format!("\"ssh_port\":{},", state.ssh_port));
+    json.push_str(&format!("\"interfaces\":[{}],", interfaces.join(",")));
+    json.push_str(&format!("\"host_keys\":[{}],", host_keys.join(",")));
+    // The login payload is already valid JSON, embed it as an object
+    json.push_str(&format!("\"login\":{}", state.login_json));
+    json.push('}');
+    json
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::login::generate_login_json;
+    use crate::tests::test_state;
+    use serde_json::Value;
+
+    fn parse(state: &DisplayState) -> Value {
+        let json = state_json(state);
+        assert!(!json.contains('\n'));
+        serde_json::from_str(&json).unwrap_or_else(|e| panic!("invalid JSON {:?}: {}", json, e))
+    }
+
+    #[test]
+    fn round_trip() {
+        let value = parse(&test_state());
+
+        assert_eq!(value["root_password"], "correct-horse-battery");
+        assert_eq!(value["onion_hostname"], "abcdefghijklmnop.onion");
+        assert_eq!(value["hostname"], "nixos");
+        assert_eq!(value["ssh_port"], 22);
+
+        let interface = &value["interfaces"][0];
+        assert_eq!(interface["name"], "eth0");
+        assert_eq!(interface["index"], 2);
+        assert_eq!(interface["operstate"], "UP");
+        assert_eq!(interface["mac"], "52:54:00:12:34:56");
+        let addrs: Vec<(&str, u64, &str, &str)> = interface["addrs"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|a| {
+                (
+                    a["addr"].as_str().unwrap(),
+                    a["prefix_len"].as_u64().unwrap(),
+                    a["family"].as_str().unwrap(),
+                    a["scope"].as_str().unwrap(),
+                )
+            })
+            .collect();
+        assert_eq!(addrs, [("192.0.2.2", 24, "inet", "global"), ("2001:db8::2", 64, "inet6", "global")]);
+
+        assert_eq!(value["host_keys"][0]["type"], "ssh-ed25519");
+        assert_eq!(value["host_keys"][0]["fingerprint"], "SHA256:abc");
+        assert_eq!(value["host_keys"][0]["key"], "ssh-ed25519 AAAA");
+
+        // Embedded as an object, not as a string holding JSON
+        assert!(value["login"].is_object());
+        assert_eq!(value["login"]["pass"], "correct-horse-battery");
+    }
+
+    #[test]
+    fn placeholders_are_null() {
+        let mut state = test_state();
+        state.root_password = WAITING_FOR_PASSWORD.to_string();
+        state.onion_hostname = WAITING_FOR_TOR.to_string();
+        state.interfaces[0].mac.clear();
+        state.login_json = generate_login_json(&state);
+
+        let value = parse(&state);
+        assert_eq!(value["root_password"], Value::Null);
+        assert_eq!(value["onion_hostname"], Value::Null);
+        assert_eq!(value["interfaces"][0]["mac"], Value::Null);
+        // The payload agrees with the rest of the document
+        assert_eq!(value["login"]["pass"], Value::Null);
+        assert_eq!(value["login"]["tor"], Value::Null);
+    }
+}
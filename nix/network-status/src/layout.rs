@@ -0,0 +1,579 @@
+//! Placement of the logo, QR code and text on a screen of any size
+//!
+//! A small box model: the screen is split into a header with the logo, the
+//! QR code and a column of text rows (section titles, their lines and the
+//! footer). Every distance is derived from the text size, so the layout
+//! keeps its proportions from 640x480 up to 4K. We start with the text size
+//! the resolution (and DPI, if known) or the configured point size calls for
+//! and shrink it until the text fits, then give the QR code all the space
+//! that is left.
+//!
+//! Landscape screens put the QR code in the left half and the text in a
+//! column on the right, everything else stacks the text below the QR code.
+
+use crate::font::Font;
+use crate::theme::Theme;
+use crate::{ansi, text, TextSegment};
+
+/// Modules of white border around the QR code required by the spec
+pub const QUIET_ZONE: usize = 4;
+
+/// Smaller QR modules are hard to scan from a monitor with a phone
+const MIN_QR_SCALE: usize = 2;
+
+/// Text sizes in pixels, see `font`
+const MIN_TEXT_SIZE: u32 = 10;
+const MAX_TEXT_SIZE: u32 = 96;
+
+/// Text size on a screen whose shorter side is 1080 pixels
+const TEXT_SIZE_1080P: u32 = 24;
+
+/// Text that is at least this large in points stays readable on high DPI
+/// panels
+const MIN_POINTS_HIGH_DPI: u32 = 11;
+
+/// Narrowest text column worth putting beside the QR code, in characters
+const MIN_BESIDE_CHARS: usize = 40;
+
+/// Screens at least this much wider than high (3:2, so 16:10 and 16:9 but
+/// not 4:3 or 5:4) get the text beside the QR code
+const LANDSCAPE_RATIO: (usize, usize) = (3, 2);
+
+/// Height of the logo in text lines, it keeps its aspect ratio
+const LOGO_LINES: usize = 4;
+
+/// A block of text below a title, lines may contain ANSI colour codes
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Section {
+    pub title: String,
+    pub lines: Vec<String>,
+}
+
+/// Everything that has to fit on the screen
+pub struct Content<'a> {
+    pub sections: &'a [Section],
+    /// Width of the QR code in modules, without the quiet zone
+    pub qr_modules: usize,
+    pub font: &'a Font,
+    /// Preferred text size in points, `None` to derive it from the resolution
+    pub font_size: Option<u32>,
+    /// The footer, logo and text colours
+    pub theme: &'a Theme,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Arrangement {
+    /// Text under the QR code
+    Below,
+    /// QR code in the left half, text in a column on the right
+    Beside,
+}
+
+/// How `compute` picks the arrangement
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Mode {
+    /// Beside on landscape screens, below otherwise, and whichever fits if
+    /// that one doesn't
+    #[default]
+    Auto,
+    Below,
+    Beside,
+}
+
+impl Mode {
+    pub const NAMES: &'static str = "auto, below or beside";
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "auto" => Some(Mode::Auto),
+            "below" => Some(Mode::Below),
+            "beside" => Some(Mode::Beside),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Rect {
+    pub x: usize,
+    pub y: usize,
+    pub width: usize,
+    pub height: usize,
+}
+
+impl Rect {
+    pub fn right(&self) -> usize {
+        self.x + self.width
+    }
+
+    pub fn bottom(&self) -> usize {
+        self.y + self.height
+    }
+
+    #[cfg(test)]
+    pub fn intersects(&self, other: &Rect) -> bool {
+        self.x < other.right() && other.x < self.right() && self.y < other.bottom() && other.y < self.bottom()
+    }
+}
+
+/// One line of text at its final position
+#[derive(Clone, Debug, PartialEq)]
+pub struct TextRow {
+    pub x: usize,
+    pub y: usize,
+    pub segments: Vec<TextSegment>,
+}
+
+impl TextRow {
+    /// Width in character cells
+    pub fn cells(&self) -> usize {
+        text::segments_width(&self.segments)
+    }
+}
+
+/// Sizes derived from the text size
+#[derive(Clone, Copy, Debug)]
+struct Metrics {
+    char_width: usize,
+    line_height: usize,
+    /// Unit for margins and gaps, a tenth of the text size
+    unit: usize,
+}
+
+#[derive(Debug)]
+pub struct Layout {
+    pub width: usize,
+    pub height: usize,
+    pub arrangement: Arrangement,
+    /// Font size in pixels, see `font`
+    pub text_size: u32,
+    /// Width of a character cell
+    pub char_width: usize,
+    pub line_height: usize,
+    /// The scaled logo, `None` if the screen is too narrow for it
+    pub logo: Option<Rect>,
+    /// The logo and its padding
+    pub logo_background: Option<Rect>,
+    /// The QR code including its quiet zone
+    pub qr: Rect,
+    /// Screen pixels per QR module
+    pub qr_scale: usize,
+    pub rows: Vec<TextRow>,
+    /// Line between the sections and the footer
+    pub separator: Rect,
+}
+
+impl Layout {
+    /// Rectangle covered by a text row
+    pub fn row_rect(&self, row: &TextRow) -> Rect {
+        Rect {
+            x: row.x,
+            y: row.y,
+            width: row.cells() * self.char_width,
+            height: self.line_height,
+        }
+    }
+}
+
+/// Text size the screen calls for before checking what fits
+fn preferred_text_size(width: usize, height: usize, dpi: Option<u32>, content: &Content) -> u32 {
+    let size = match content.font_size {
+        Some(points) => content.font.size_for_points(points, dpi.unwrap_or(96)),
+        None => {
+            let mut size = width.min(height) as u32 * TEXT_SIZE_1080P / 1080;
+            // Small high resolution panels, e.g. laptops, need more
+            if let Some(dpi) = dpi.filter(|&dpi| dpi >= 144) {
+                size = size.max(content.font.size_for_points(MIN_POINTS_HIGH_DPI, dpi));
+            }
+            size
+        }
+    };
+    size.clamp(MIN_TEXT_SIZE, MAX_TEXT_SIZE)
+}
+
+/// Lay out `content` on a `width` x `height` screen
+pub fn compute(width: usize, height: usize, dpi: Option<u32>, mode: Mode, content: &Content) -> Layout {
+    let landscape = width * LANDSCAPE_RATIO.1 >= height * LANDSCAPE_RATIO.0;
+    let candidates: &[Arrangement] = match mode {
+        Mode::Auto if landscape => &[Arrangement::Beside, Arrangement::Below],
+        Mode::Auto => &[Arrangement::Below, Arrangement::Beside],
+        Mode::Below => &[Arrangement::Below],
+        Mode::Beside => &[Arrangement::Beside],
+    };
+
+    for text_size in (MIN_TEXT_SIZE..=preferred_text_size(width, height, dpi, content)).rev() {
+        for &arrangement in candidates {
+            let layout = arrange(width, height, text_size, arrangement, content);
+            let column_chars = layout.separator.width / layout.char_width;
+            let wide_enough = arrangement == Arrangement::Below || column_chars >= MIN_BESIDE_CHARS;
+            if layout.qr_scale >= MIN_QR_SCALE && fits(&layout) && wide_enough {
+                return layout;
+            }
+        }
+    }
+    // Nothing fits, show as much as possible
+    arrange(width, height, MIN_TEXT_SIZE, candidates[0], content)
+}
+
+/// Whether all text is on screen
+fn fits(layout: &Layout) -> bool {
+    layout.rows.iter().all(|row| {
+        let rect = layout.row_rect(row);
+        rect.right() <= layout.width && rect.bottom() <= layout.height
+    })
+}
+
+/// Place everything for a fixed text scale and arrangement, the QR code gets
+/// whatever space remains (possibly none, then `qr_scale` is 0)
+fn arrange(width: usize, height: usize, text_size: u32, arrangement: Arrangement, content: &Content) -> Layout {
+    let metrics = Metrics {
+        char_width: content.font.cell_width(text_size).max(1),
+        // Some leading, the font's line height has none
+        line_height: content.font.line_height(text_size) + text_size as usize / 5,
+        unit: (text_size as usize / 10).max(1),
+    };
+    let char_width = metrics.char_width;
+    let margin = 15 * metrics.unit;
+    let gap = 5 * metrics.unit;
+    // Only a logo on its own background needs room around it
+    let logo_padding = if content.theme.logo_background.is_some() { 5 * metrics.unit } else { 0 };
+    let qr_modules = content.qr_modules + 2 * QUIET_ZONE;
+
+    // Left edge of the text, the logo sits on top of it
+    let column_x = match arrangement {
+        Arrangement::Below => margin,
+        Arrangement::Beside => width / 2 + margin / 2,
+    };
+    let text_width = width.saturating_sub(column_x + margin);
+
+    let logo_size = content.theme.logo.as_ref().and_then(|logo| {
+        fit_logo((logo.width, logo.height), LOGO_LINES * metrics.line_height, text_width.saturating_sub(2 * logo_padding))
+    });
+    let (logo, logo_background) = match logo_size {
+        Some((logo_width, logo_height)) => {
+            let background = Rect {
+                x: column_x,
+                y: margin,
+                width: logo_width + 2 * logo_padding,
+                height: logo_height + 2 * logo_padding,
+            };
+            let logo = Rect {
+                x: column_x + logo_padding,
+                y: margin + logo_padding,
+                width: logo_width,
+                height: logo_height,
+            };
+            (Some(logo), Some(background))
+        }
+        None => (None, None),
+    };
+    let header_bottom = logo_background.map_or(0, |b| b.bottom());
+    let logo_right = logo_background.map_or(0, |b| b.right());
+
+    let fit_qr = |max_width: usize, max_height: usize| max_width.min(max_height) / qr_modules;
+
+    let (qr, qr_scale, text_top) = match arrangement {
+        Arrangement::Below => {
+            let text_height = place_text(content, text_width / char_width, metrics).2;
+
+            // Either centered next to the logo, or centered under it
+            let room_below = |top: usize| height.saturating_sub(top + gap + text_height + margin);
+            let next_to_logo = fit_qr(width.saturating_sub(2 * (logo_right + margin)), room_below(margin));
+            let under_logo = fit_qr(text_width, room_below(header_bottom + gap));
+            let (scale, top) = if next_to_logo >= under_logo {
+                (next_to_logo, margin)
+            } else {
+                (under_logo, header_bottom + gap)
+            };
+
+            let size = scale * qr_modules;
+            let qr = Rect { x: width.saturating_sub(size) / 2, y: top, width: size, height: size };
+            (qr, scale, qr.bottom().max(header_bottom) + gap)
+        }
+        Arrangement::Beside => {
+            // As large as the left half allows, centered in it
+            let half = column_x.saturating_sub(2 * margin);
+            let scale = fit_qr(half, height.saturating_sub(2 * margin));
+            let size = scale * qr_modules;
+            let qr = Rect {
+                x: margin + (half - size) / 2,
+                y: height.saturating_sub(size) / 2,
+                width: size,
+                height: size,
+            };
+            let text_top = logo_background.map_or(margin, |b| b.bottom() + gap);
+            (qr, scale, text_top)
+        }
+    };
+
+    let (mut rows, mut separator, _) = place_text(content, text_width / char_width, metrics);
+    for row in &mut rows {
+        row.x += column_x;
+        row.y += text_top;
+    }
+    separator.x += column_x;
+    separator.y += text_top;
+    separator.width = text_width;
+
+    Layout {
+        width,
+        height,
+        arrangement,
+        text_size,
+        char_width,
+        line_height: metrics.line_height,
+        logo,
+        logo_background,
+        qr,
+        qr_scale,
+        rows,
+        separator,
+    }
+}
+
+/// Size of a logo `natural` pixels large scaled to `height`, or narrower if
+/// that is wider than `max_width`; `None` if nothing would be left of it
+fn fit_logo(natural: (usize, usize), height: usize, max_width: usize) -> Option<(usize, usize)> {
+    let (natural_width, natural_height) = natural;
+    let mut size = ((height * natural_width).div_ceil(natural_height), height);
+    if size.0 > max_width {
+        size = (max_width, max_width * natural_height / natural_width);
+    }
+    (size.0 > 0 && size.1 > 0).then_some(size)
+}
+
+/// Text rows and the separator relative to the top left corner of a column
+/// `max_cells` character cells wide, plus the total height
+fn place_text(content: &Content, max_cells: usize, metrics: Metrics) -> (Vec<TextRow>, Rect, usize) {
+    let Metrics { char_width, line_height, unit } = metrics;
+    let gap = 5 * unit;
+    let max_cells = max_cells.max(1);
+
+    let mut rows = Vec::new();
+    let mut y = 0;
+    for section in content.sections {
+        rows.push(TextRow {
+            x: 0,
+            y,
+            segments: vec![TextSegment::new(section.title.clone(), content.theme.accent)],
+        });
+        y += line_height + gap;
+
+        // Content is indented by two characters, wrapped lines by two more
+        for line in &section.lines {
+            for segments in text::wrap(&ansi::parse(line, content.theme.foreground), max_cells.saturating_sub(2).max(1), 2) {
+                rows.push(TextRow { x: 2 * char_width, y, segments });
+                y += line_height;
+            }
+        }
+        y += gap;
+    }
+
+    let separator = Rect {
+        x: 0,
+        y: y + gap,
+        width: max_cells * char_width,
+        height: unit.max(2),
+    };
+    y = separator.bottom() + 2 * gap;
+    for segments in text::wrap(&ansi::parse(&content.theme.footer, content.theme.foreground), max_cells, 0) {
+        rows.push(TextRow { x: 0, y, segments });
+        y += line_height;
+    }
+    (rows, separator, y)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fmt::Write as _;
+    use std::path::PathBuf;
+
+    fn sections() -> Vec<Section> {
+        vec![
+            Section {
+                title: "Login Credentials".to_string(),
+                lines: vec!["Root password: correct-horse-battery".to_string()],
+            },
+            Section {
+                title: "Network Information".to_string(),
+                lines: vec![
+                    "\x1b[36meth0            \x1b[0m \x1b[32mUP            \x1b[0m \x1b[33m52:54:00:12:34:56\x1b[0m \
+                     \x1b[35m192.0.2.2\x1b[0m/24 \x1b[34m2001:db8::2\x1b[0m/64 \x1b[34mfe80::5054:ff:fe12:3456\x1b[0m/64"
+                        .to_string(),
+                    "\x1b[36mwlan0           \x1b[0m \x1b[32mUP            \x1b[0m \x1b[35m198.51.100.7\x1b[0m/24"
+                        .to_string(),
+                ],
+            },
+            Section {
+                title: "Remote Access".to_string(),
+                lines: vec![
+                    "Tor Hidden Service: abcdefghijklmnopqrstuvwxyzabcdefghijklmnopqrstuvwxyzab.onion".to_string(),
+                    "Multicast DNS: nixos.local".to_string(),
+                ],
+            },
+            Section {
+                title: "Host Keys".to_string(),
+                lines: vec![
+                    "ED25519: SHA256:iLpeOnMjDsXBVPVJloQt5RLHH9S0iKWJl/dBnej1PMI".to_string(),
+                    "RSA:     SHA256:3Hxs2xUTkyT9MzWe/mQPdjQC5CS6yxzsNIaNuqW5SDE".to_string(),
+                ],
+            },
+        ]
+    }
+
+    const RESOLUTIONS: [(usize, usize); 9] = [
+        (320, 240),
+        (640, 480),
+        (800, 600),
+        (1024, 768),
+        (1280, 720),
+        (1920, 1080),
+        (3840, 2160),
+        (600, 1024),
+        (1080, 1920),
+    ];
+
+    fn layout(width: usize, height: usize) -> Layout {
+        layout_with(width, height, Mode::Auto)
+    }
+
+    fn layout_with(width: usize, height: usize, mode: Mode) -> Layout {
+        let sections = sections();
+        let content = Content {
+            sections: &sections,
+            // Version 10 QR code, about the size of a login payload with a few addresses
+            qr_modules: 57,
+            font: &Font::embedded(),
+            font_size: None,
+            theme: &Theme::default(),
+        };
+        compute(width, height, None, mode, &content)
+    }
+
+    fn describe(layout: &Layout) -> String {
+        let mut s = String::new();
+        writeln!(
+            s,
+            "{}x{} {:?} text_size={} cell={}x{} qr_scale={}",
+            layout.width,
+            layout.height,
+            layout.arrangement,
+            layout.text_size,
+            layout.char_width,
+            layout.line_height,
+            layout.qr_scale
+        )
+        .unwrap();
+        let rect = |r: &Rect| format!("{},{} {}x{}", r.x, r.y, r.width, r.height);
+        match &layout.logo {
+            Some(logo) => writeln!(s, "logo {}", rect(logo)).unwrap(),
+            None => writeln!(s, "logo none").unwrap(),
+        }
+        writeln!(s, "qr {}", rect(&layout.qr)).unwrap();
+        writeln!(s, "separator {}", rect(&layout.separator)).unwrap();
+        for row in &layout.rows {
+            let text: String = row.segments.iter().map(|s| s.text.as_str()).collect();
+            writeln!(s, "row {},{} {:?}", row.x, row.y, text).unwrap();
+        }
+        s
+    }
+
+    /// Compare against `src/snapshots/`, `UPDATE_SNAPSHOTS=1` rewrites them
+    #[test]
+    fn snapshots() {
+        let dir = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("src/snapshots");
+        let update = std::env::var_os("UPDATE_SNAPSHOTS").is_some();
+        let mut mismatches = Vec::new();
+
+        let mut cases: Vec<(usize, usize, Mode, String)> = RESOLUTIONS
+            .iter()
+            .map(|&(width, height)| (width, height, Mode::Auto, format!("layout-{}x{}.txt", width, height)))
+            .collect();
+        cases.push((1920, 1080, Mode::Below, "layout-1920x1080-below.txt".to_string()));
+        cases.push((1024, 768, Mode::Beside, "layout-1024x768-beside.txt".to_string()));
+
+        for (width, height, mode, name) in cases {
+            let actual = describe(&layout_with(width, height, mode));
+            let path = dir.join(name);
+            if update {
+                std::fs::create_dir_all(&dir).unwrap();
+                std::fs::write(&path, &actual).unwrap();
+                continue;
+            }
+            let expected = std::fs::read_to_string(&path).unwrap_or_default();
+            if actual != expected {
+                mismatches.push(format!("{}:\n{}", path.display(), actual));
+            }
+        }
+        assert!(mismatches.is_empty(), "layout changed, rerun with UPDATE_SNAPSHOTS=1 if intended:\n{}", mismatches.join("\n"));
+    }
+
+    #[test]
+    fn everything_on_screen_without_overlap() {
+        for (width, height) in RESOLUTIONS {
+            let layout = layout(width, height);
+            let name = format!("{}x{}", width, height);
+            // 320x240 can't fit everything, it only has to degrade gracefully
+            if width >= 600 {
+                assert!(layout.qr_scale >= MIN_QR_SCALE, "{}: QR scale {}", name, layout.qr_scale);
+                assert!(fits(&layout), "{}: text runs off screen", name);
+            }
+            assert!(layout.qr.right() <= width && layout.qr.bottom() <= height, "{}: QR off screen", name);
+
+            let mut boxes: Vec<(String, Rect)> = vec![("qr".to_string(), layout.qr)];
+            if let Some(background) = layout.logo_background {
+                boxes.push(("logo".to_string(), background));
+            }
+            for row in &layout.rows {
+                let rect = layout.row_rect(row);
+                for (what, other) in &boxes {
+                    assert!(!rect.intersects(other), "{}: row at {},{} overlaps {}", name, row.x, row.y, what);
+                }
+            }
+            if let Some(background) = layout.logo_background {
+                assert!(!background.intersects(&layout.qr), "{}: logo overlaps QR", name);
+            }
+        }
+    }
+
+    #[test]
+    fn arrangement_follows_aspect_ratio_unless_forced() {
+        assert_eq!(layout(1920, 1080).arrangement, Arrangement::Beside);
+        assert_eq!(layout(1280, 800).arrangement, Arrangement::Beside);
+        assert_eq!(layout(1024, 768).arrangement, Arrangement::Below);
+        assert_eq!(layout(1080, 1920).arrangement, Arrangement::Below);
+        assert_eq!(layout_with(1920, 1080, Mode::Below).arrangement, Arrangement::Below);
+        assert_eq!(layout_with(1024, 768, Mode::Beside).arrangement, Arrangement::Beside);
+
+        // The QR code fills the left half, the text stays in the right one
+        let beside = layout(1920, 1080);
+        assert!(beside.qr.width * 10 >= 1920 / 2 * 9, "QR code is only {}px", beside.qr.width);
+        assert!(beside.qr.right() <= 1920 / 2);
+        assert!(beside.rows.iter().all(|row| row.x >= 1920 / 2));
+    }
+
+    #[test]
+    fn text_size_grows_with_resolution() {
+        assert_eq!(layout(1920, 1080).text_size, 24);
+        assert_eq!(layout(3840, 2160).text_size, 48);
+        assert!(layout(640, 480).text_size < 16);
+
+        let font = Font::embedded();
+        let sections = sections();
+        let theme = Theme::default();
+        let content = |font_size| Content {
+            sections: &sections,
+            qr_modules: 57,
+            font: &font,
+            font_size,
+            theme: &theme,
+        };
+        // A 10" 2560x1600 tablet is about 300 DPI
+        assert_eq!(preferred_text_size(2560, 1600, Some(300), &content(None)), 53);
+        assert_eq!(preferred_text_size(2560, 1600, Some(96), &content(None)), 35);
+        // A configured size is in points, so it depends on the DPI too
+        assert_eq!(preferred_text_size(1920, 1080, None, &content(Some(12))), 19);
+        assert_eq!(preferred_text_size(1920, 1080, Some(192), &content(Some(12))), 37);
+    }
+}
@@ -0,0 +1,289 @@
+//! Login payload encoded in the QR code
+//!
+//! The payload is a single JSON object. Version 1 of the schema:
+//!
+//! ```json
+//! {
+//!   "version": 1,
+//!   "pass": "root password",
+//!   "tor": "xxxxxxxx.onion",
+//!   "hostname": "nixos",
+//!   "mdns": "nixos.local",
+//!   "port": 22,
+//!   "addrs": [
+//!     { "addr": "192.0.2.2", "iface": "eth0", "family": "inet" },
+//!     { "addr": "fe80::1", "iface": "eth0", "family": "inet6" }
+//!   ],
+//!   "host_keys": [
+//!     {
+//!       "type": "ssh-ed25519",
+//!       "fingerprint": "SHA256:...",
+//!       "key": "ssh-ed25519 AAAA..."
+//!     }
+//!   ]
+//! }
+//! ```
+//!
+//! `pass` and `tor` are `null` until the password file and the onion service's
+//! hostname exist. `addrs` only contains addresses a peer can connect to right
+//! now (no loopback, nothing still in duplicate address detection).
+//! `host_keys[].key`
+//! can be combined with an address into a known_hosts line. It is left out
+//! of the QR code when the payload would be too large for one, RSA keys
+//! first, so scanners must cope with only a `fingerprint`. Consumers should
+//! ignore unknown fields; incompatible changes bump `version`.
+
+use qrcode::QrCode;
+
+use crate::json::{json_string, json_string_or_null};
+use crate::ssh::HostKey;
+use crate::{DisplayState, WAITING_FOR_PASSWORD, WAITING_FOR_TOR};
+
+pub const LOGIN_JSON_VERSION: u32 = 1;
+
+/// Shown when not even the smallest payload fits
+const PLACEHOLDER: &str = r#"{"status": "waiting"}"#;
+
+pub fn generate_login_json(state: &DisplayState) -> String {
+    payload(state, |_| true)
+}
+
+/// The QR code for `state`'s login payload
+///
+/// Public keys are dropped until it fits, RSA keys first; what was dropped
+/// is logged.
+pub fn qr_code(state: &DisplayState) -> QrCode {
+    if let Ok(code) = QrCode::new(&state.login_json) {
+        return code;
+    }
+    for (dropped, keep_others) in [("RSA host keys", true), ("host keys", false)] {
+        let keep_key = |key: &HostKey| keep_others && key.algorithm != "ssh-rsa";
+        if let Ok(code) = QrCode::new(payload(state, keep_key)) {
+            eprintln!("Login payload too large for a QR code, leaving out the public {}", dropped);
+            return code;
+        }
+    }
+    eprintln!("Login payload too large for a QR code even without public keys");
+    QrCode::new(PLACEHOLDER).unwrap()
+}
+
+/// The login payload, with `key` only for the host keys `keep_key` accepts
+fn payload(state: &DisplayState, keep_key: impl Fn(&HostKey) -> bool) -> String {
+    // Only addresses a remote peer can actually connect to, without prefix length
+    let addrs: Vec<String> = state
+        .interfaces
+        .iter()
+        .flat_map(|interface| {
+            interface
+                .addrs
+                .iter()
+                .filter(|addr| addr.is_usable())
+                .map(move |addr| {
+                    format!(
+                        "{{\"addr\":{},\"iface\":{},\"family\":\"{}\"}}",
+                        json_string(&addr.addr.to_string()),
+                        json_string(&interface.name),
+                        addr.family.as_str()
+                    )
+                })
+        })
+        .collect();
+
+    let host_keys: Vec<String> = state
+        .host_keys
+        .iter()
+        .map(|key| {
+            let public_key = if keep_key(key) {
+                format!(",\"key\":{}", json_string(&key.public_key))
+            } else {
+                String::new()
+            };
+            format!(
+                "{{\"type\":{},\"fingerprint\":{}{}}}",
+                json_string(&key.algorithm),
+                json_string(&key.fingerprint),
+                public_key
+            )
+        })
+        .collect();
+
+    // Generate JSON manually
+    let mut json = String::from("{");
+    json.push_str(&format!("\"version\":{},", LOGIN_JSON_VERSION));
+    json.push_str(&format!("\"pass\":{},", json_string_or_null(&state.root_password, WAITING_FOR_PASSWORD)));
+    json.push_str(&format!("\"tor\":{},", json_string_or_null(&state.onion_hostname, WAITING_FOR_TOR)));
+    json.push_str(&format!("\"hostname\":{},", json_string(&state.hostname)));
+    json.push_str(&format!("\"mdns\":{},", json_string(&format!("{}.local", state.hostname))));
+    json.push_str(&format!("\"port\":{},", state.ssh_port));
+    json.push_str(&format!("\"addrs\":[{}],", addrs.join(",")));
+    json.push_str(&format!("\"host_keys\":[{}]", host_keys.join(",")));
+    json.push('}');
+    json
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::netlink::{Address, Family, Scope};
+    use crate::tests::test_state;
+    use serde_json::Value;
+    use std::net::IpAddr;
+
+    fn address(addr: &str, prefix_len: u8, scope: Scope, flags: u32) -> Address {
+        let addr: IpAddr = addr.parse().unwrap();
+        let family = if addr.is_ipv4() { Family::Inet } else { Family::Inet6 };
+        Address { family, addr, prefix_len, scope, flags }
+    }
+
+    fn state_with_password(password: &str) -> DisplayState {
+        let mut state = test_state();
+        state.root_password = password.to_string();
+        state.ssh_port = 2222;
+        state.interfaces[0].addrs.extend([
+            // tentative, not reachable yet
+            address("2001:db8::3", 64, Scope::Universe, 0x40),
+            address("fe80::1", 64, Scope::Link, 0),
+        ]);
+        state
+    }
+
+    fn parse(state: &DisplayState) -> Value {
+        let json = generate_login_json(state);
+        serde_json::from_str(&json).unwrap_or_else(|e| panic!("invalid JSON {:?}: {}", json, e))
+    }
+
+    #[test]
+    fn schema() {
+        let value = parse(&state_with_password("correct-horse-battery"));
+
+        assert_eq!(value["version"], LOGIN_JSON_VERSION);
+        assert_eq!(value["pass"], "correct-horse-battery");
+        assert_eq!(value["tor"], "abcdefghijklmnop.onion");
+        assert_eq!(value["hostname"], "nixos");
+        assert_eq!(value["mdns"], "nixos.local");
+        assert_eq!(value["port"], 2222);
+
+        let addrs = value["addrs"].as_array().unwrap();
+        let addrs: Vec<(&str, &str, &str)> = addrs
+            .iter()
+            .map(|a| {
+                (
+                    a["addr"].as_str().unwrap(),
+                    a["iface"].as_str().unwrap(),
+                    a["family"].as_str().unwrap(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            addrs,
+            vec![
+                ("192.0.2.2", "eth0", "inet"),
+                ("2001:db8::2", "eth0", "inet6"),
+                ("fe80::1", "eth0", "inet6"),
+            ]
+        );
+
+        assert_eq!(value["host_keys"][0]["type"], "ssh-ed25519");
+        assert_eq!(value["host_keys"][0]["fingerprint"], "SHA256:abc");
+        assert_eq!(value["host_keys"][0]["key"], "ssh-ed25519 AAAA");
+    }
+
+    #[test]
+    fn placeholders_are_null() {
+        let mut state = state_with_password(WAITING_FOR_PASSWORD);
+        state.onion_hostname = WAITING_FOR_TOR.to_string();
+        let value = parse(&state);
+        assert_eq!(value["pass"], Value::Null);
+        assert_eq!(value["tor"], Value::Null);
+    }
+
+    #[test]
+    fn large_payloads_drop_public_keys() {
+        let mut state = test_state();
+        let addrs = &mut state.interfaces[0].addrs;
+        for i in 0..20 {
+            addrs.push(address(&format!("2001:db8:0:1:a2b4:c6d8:e0f2:{:x}", i), 64, Scope::Universe, 0));
+        }
+        // What RSA-4096 looks like, next to the ed25519 key
+        state.host_keys.insert(0, HostKey {
+            algorithm: "ssh-rsa".to_string(),
+            public_key: format!("ssh-rsa AAAAB3NzaC1yc2E{}", "A".repeat(697)),
+            fingerprint: "SHA256:rsa".to_string(),
+        });
+        state.login_json = generate_login_json(&state);
+        assert!(QrCode::new(&state.login_json).is_err(), "{} bytes fit", state.login_json.len());
+
+        let expected = payload(&state, |key| key.algorithm != "ssh-rsa");
+        assert_eq!(qr_code(&state).to_colors(), QrCode::new(&expected).unwrap().to_colors());
+        let value: Value = serde_json::from_str(&expected).unwrap();
+        assert_eq!(value["addrs"].as_array().unwrap().len(), 22);
+        assert_eq!(value["host_keys"][0]["fingerprint"], "SHA256:rsa");
+        assert_eq!(value["host_keys"][0].get("key"), None);
+        assert_eq!(value["host_keys"][1]["key"], "ssh-ed25519 AAAA");
+
+        // Nothing to drop anymore
+        state.interfaces[0].name = "x".repeat(400);
+        state.login_json = generate_login_json(&state);
+        assert_eq!(qr_code(&state).to_colors(), QrCode::new(PLACEHOLDER).unwrap().to_colors());
+    }
+
+    #[test]
+    fn empty_lists() {
+        let mut state = state_with_password("x");
+        state.interfaces.clear();
+        state.host_keys.clear();
+        let value = parse(&state);
+        assert_eq!(value["addrs"], Value::Array(Vec::new()));
+        assert_eq!(value["host_keys"], Value::Array(Vec::new()));
+    }
+
+    #[test]
+    fn escaping_special_characters() {
+        for password in [
+            "",
+            "\"",
+            "\\",
+            "\\\"",
+            "quote\"inside",
+            "new\nline\r\ttab",
+            "\u{0}\u{1}\u{1f}\u{7f}",
+            "</script>",
+            "grüße-пароль-密码-🔑",
+            "\u{2028}\u{2029}\u{feff}",
+        ] {
+            let value = parse(&state_with_password(password));
+            assert_eq!(value["pass"], password);
+        }
+    }
+
+    #[test]
+    fn escaping_arbitrary_unicode() {
+        // Deterministic xorshift so failures are reproducible
+        let mut seed: u32 = 0x9e37_79b9;
+        let mut next = move || {
+            seed ^= seed << 13;
+            seed ^= seed >> 17;
+            seed ^= seed << 5;
+            seed
+        };
+
+        for _ in 0..2000 {
+            let len = next() % 32;
+            let password: String = (0..len)
+                .filter_map(|_| {
+                    // Bias towards ASCII and control characters, but cover all planes
+                    let cp = match next() % 4 {
+                        0 => next() % 0x80,
+                        1 => next() % 0x800,
+                        2 => next() % 0x10000,
+                        _ => next() % 0x110000,
+                    };
+                    char::from_u32(cp)
+                })
+                .collect();
+
+            let value = parse(&state_with_password(&password));
+            assert_eq!(value["pass"], password.as_str());
+        }
+    }
+}
@@ -0,0 +1,321 @@
+//! The logo above the text
+//!
+//! Loaded from a PNG, an SVG or raw RGBA, and scaled to whatever box the
+//! layout gives it: SVGs are rasterised at that size, bitmaps are resampled
+//! bilinearly. Scaled bitmaps are cached, a screen keeps its size until the
+//! mode changes.
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt;
+use std::io::{self, BufReader};
+use std::path::Path;
+use std::rc::Rc;
+
+use resvg::{tiny_skia, usvg};
+
+/// `clan-logo.svg`, or the PNG or SVG `NETWORK_STATUS_LOGO` named at build
+/// time, see `build.rs`
+const BUILT_IN: &[u8] = include_bytes!(env!("NETWORK_STATUS_LOGO"));
+
+const PNG_SIGNATURE: &[u8] = b"\x89PNG\r\n\x1a\n";
+
+/// Row-major RGBA pixels
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Bitmap {
+    pub width: usize,
+    pub height: usize,
+    /// 4 bytes per pixel, not premultiplied
+    pub rgba: Vec<u8>,
+}
+
+enum Image {
+    Bitmap(Bitmap),
+    Svg(Box<usvg::Tree>),
+}
+
+pub struct Logo {
+    /// Natural size, SVGs at the size their document asks for
+    pub width: usize,
+    pub height: usize,
+    image: Image,
+    scaled: RefCell<HashMap<(usize, usize), Rc<Bitmap>>>,
+}
+
+impl fmt::Debug for Logo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Logo")
+            .field("width", &self.width)
+            .field("height", &self.height)
+            .finish_non_exhaustive()
+    }
+}
+
+fn invalid(message: impl Into<Box<dyn std::error::Error + Send + Sync>>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+impl Logo {
+    pub fn built_in() -> Self {
+        Logo::decode(BUILT_IN.to_vec(), false, None).expect("built-in logo is valid")
+    }
+
+    fn from_bitmap(bitmap: Bitmap) -> Self {
+        Logo {
+            width: bitmap.width,
+            height: bitmap.height,
+            image: Image::Bitmap(bitmap),
+            scaled: RefCell::new(HashMap::new()),
+        }
+    }
+
+    /// Load a PNG or SVG, or raw RGBA if `size` is given
+    pub fn load(path: &Path, size: Option<(usize, usize)>) -> io::Result<Self> {
+        let data = std::fs::read(path)?;
+        let is_svg = path
+            .extension()
+            .is_some_and(|e| e.eq_ignore_ascii_case("svg") || e.eq_ignore_ascii_case("svgz"));
+        Logo::decode(data, is_svg, size)
+    }
+
+    /// SVGs are also recognised by their leading `<`
+    fn decode(data: Vec<u8>, is_svg: bool, size: Option<(usize, usize)>) -> io::Result<Self> {
+        let is_svg = is_svg || data.trim_ascii_start().starts_with(b"<");
+        let logo = match size {
+            Some((width, height)) => {
+                let len = width.checked_mul(height).and_then(|n| n.checked_mul(4));
+                let len = len.ok_or_else(|| invalid(format!("{}x{} RGBA is too large", width, height)))?;
+                if data.len() != len {
+                    return Err(invalid(format!("{} bytes is not {}x{} RGBA", data.len(), width, height)));
+                }
+                Logo::from_bitmap(Bitmap { width, height, rgba: data })
+            }
+            None if data.starts_with(PNG_SIGNATURE) => Logo::from_bitmap(decode_png(&data)?),
+            None if is_svg => {
+                let tree = usvg::Tree::from_data(&data, &usvg::Options::default()).map_err(invalid)?;
+                Logo {
+                    width: tree.size().width().ceil() as usize,
+                    height: tree.size().height().ceil() as usize,
+                    image: Image::Svg(Box::new(tree)),
+                    scaled: RefCell::new(HashMap::new()),
+                }
+            }
+            None => return Err(invalid("not a PNG or SVG, raw RGBA needs logo_width and logo_height")),
+        };
+        if logo.width == 0 || logo.height == 0 {
+            return Err(invalid("the logo is empty"));
+        }
+        Ok(logo)
+    }
+
+    /// The logo stretched to `width` x `height`
+    pub fn scaled(&self, width: usize, height: usize) -> Rc<Bitmap> {
+        self.scaled
+            .borrow_mut()
+            .entry((width, height))
+            .or_insert_with(|| {
+                Rc::new(match &self.image {
+                    Image::Bitmap(bitmap) => bitmap.resize(width, height),
+                    Image::Svg(tree) => rasterize(tree, width, height),
+                })
+            })
+            .clone()
+    }
+}
+
+fn decode_png(data: &[u8]) -> io::Result<Bitmap> {
+    let mut decoder = png::Decoder::new(BufReader::new(io::Cursor::new(data)));
+    // 8 bits per channel, palettes and tRNS expanded
+    decoder.set_transformations(png::Transformations::normalize_to_color8());
+    let mut reader = decoder.read_info().map_err(invalid)?;
+    let mut buffer = vec![0; reader.output_buffer_size().unwrap_or(0)];
+    let info = reader.next_frame(&mut buffer).map_err(invalid)?;
+    let pixels = &buffer[..info.buffer_size()];
+
+    let rgba = match info.color_type {
+        png::ColorType::Rgba => pixels.to_vec(),
+        png::ColorType::Rgb => pixels.chunks_exact(3).flat_map(|p| [p[0], p[1], p[2], 0xFF]).collect(),
+        png::ColorType::GrayscaleAlpha => pixels.chunks_exact(2).flat_map(|p| [p[0], p[0], p[0], p[1]]).collect(),
+        png::ColorType::Grayscale => pixels.iter().flat_map(|&v| [v, v, v, 0xFF]).collect(),
+        png::ColorType::Indexed => return Err(invalid("palette was not expanded")),
+    };
+    Ok(Bitmap { width: info.width as usize, height: info.height as usize, rgba })
+}
+
+fn rasterize(tree: &usvg::Tree, width: usize, height: usize) -> Bitmap {
+    let Some(mut pixmap) = tiny_skia::Pixmap::new(width as u32, height as u32) else {
+        return Bitmap { width: 0, height: 0, rgba: Vec::new() };
+    };
+    let size = tree.size();
+    let transform = tiny_skia::Transform::from_scale(width as f32 / size.width(), height as f32 / size.height());
+    resvg::render(tree, transform, &mut pixmap.as_mut());
+
+    let rgba = pixmap
+        .pixels()
+        .iter()
+        .flat_map(|p| {
+            let c = p.demultiply();
+            [c.red(), c.green(), c.blue(), c.alpha()]
+        })
+        .collect();
+    Bitmap { width, height, rgba }
+}
+
+impl Bitmap {
+    fn pixel(&self, x: usize, y: usize) -> [f32; 4] {
+        let p = &self.rgba[(y * self.width + x) * 4..][..4];
+        let alpha = p[3] as f32 / 255.0;
+        [p[0] as f32 * alpha, p[1] as f32 * alpha, p[2] as f32 * alpha, p[3] as f32]
+    }
+
+    /// Bilinear resampling, on premultiplied colours so that transparent
+    /// pixels don't darken the edges
+    ///
+    /// Large reductions first halve the bitmap with a box filter, bilinear
+    /// sampling alone would skip most of the source pixels.
+    fn resize(&self, width: usize, height: usize) -> Bitmap {
+        if width * 2 <= self.width && height * 2 <= self.height {
+            return self.halve().resize(width, height);
+        }
+
+        let mut rgba = Vec::with_capacity(width * height * 4);
+        // Position of the target pixel's centre in the source, and the two
+        // source pixels around it with the weight of the second
+        let sample = |i: usize, target: usize, source: usize| {
+            let pos = ((i as f32 + 0.5) * source as f32 / target as f32 - 0.5).clamp(0.0, (source - 1) as f32);
+            let first = pos.floor() as usize;
+            (first, (first + 1).min(source - 1), pos - first as f32)
+        };
+        for y in 0..height {
+            let (y0, y1, fy) = sample(y, height, self.height);
+            for x in 0..width {
+                let (x0, x1, fx) = sample(x, width, self.width);
+                let mut p = [0.0; 4];
+                for (sx, sy, weight) in [
+                    (x0, y0, (1.0 - fx) * (1.0 - fy)),
+                    (x1, y0, fx * (1.0 - fy)),
+                    (x0, y1, (1.0 - fx) * fy),
+                    (x1, y1, fx * fy),
+                ] {
+                    for (c, v) in p.iter_mut().zip(self.pixel(sx, sy)) {
+                        *c += v * weight;
+                    }
+                }
+                rgba.extend(unpremultiply(p));
+            }
+        }
+        Bitmap { width, height, rgba }
+    }
+
+    fn halve(&self) -> Bitmap {
+        let (width, height) = (self.width / 2, self.height / 2);
+        let mut rgba = Vec::with_capacity(width * height * 4);
+        for y in 0..height {
+            for x in 0..width {
+                let mut p = [0.0; 4];
+                for (sx, sy) in [(2 * x, 2 * y), (2 * x + 1, 2 * y), (2 * x, 2 * y + 1), (2 * x + 1, 2 * y + 1)] {
+                    for (c, v) in p.iter_mut().zip(self.pixel(sx, sy)) {
+                        *c += v / 4.0;
+                    }
+                }
+                rgba.extend(unpremultiply(p));
+            }
+        }
+        Bitmap { width, height, rgba }
+    }
+}
+
+fn unpremultiply([r, g, b, a]: [f32; 4]) -> [u8; 4] {
+    if a < 0.5 {
+        return [0; 4];
+    }
+    let alpha = a / 255.0;
+    let channel = |v: f32| (v / alpha).round().clamp(0.0, 255.0) as u8;
+    [channel(r), channel(g), channel(b), a.round() as u8]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_file(name: &str, contents: &[u8]) -> std::path::PathBuf {
+        let path = std::env::temp_dir().join(format!("network-status-{}-{}", std::process::id(), name));
+        std::fs::write(&path, contents).unwrap();
+        path
+    }
+
+    #[test]
+    fn built_in_logo() {
+        let logo = Logo::built_in();
+        assert!(logo.width > 0 && logo.height > 0);
+        assert!(logo.scaled(logo.width, logo.height).rgba.chunks_exact(4).any(|p| p[3] == 255));
+    }
+
+    #[test]
+    fn png_logo() {
+        let mut data = Vec::new();
+        let mut encoder = png::Encoder::new(&mut data, 2, 2);
+        encoder.set_color(png::ColorType::GrayscaleAlpha);
+        let mut writer = encoder.write_header().unwrap();
+        writer.write_image_data(&[0, 255, 64, 128, 128, 0, 255, 255]).unwrap();
+        writer.finish().unwrap();
+        let path = temp_file("logo.png", &data);
+
+        let logo = Logo::load(&path, None).unwrap();
+        assert_eq!((logo.width, logo.height), (2, 2));
+        assert_eq!(&logo.scaled(2, 2).rgba[4..8], [64, 64, 64, 128]);
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn svg_logo_is_rasterized_at_any_size() {
+        let svg = br#"<svg xmlns="http://www.w3.org/2000/svg" width="20" height="10">
+            <rect width="10" height="10" fill="red"/></svg>"#;
+        let path = temp_file("logo.svg", svg);
+        let logo = Logo::load(&path, None).unwrap();
+        assert_eq!((logo.width, logo.height), (20, 10));
+
+        let big = logo.scaled(200, 100);
+        assert_eq!(&big.rgba[..4], [255, 0, 0, 255]);
+        // The right half is transparent
+        assert_eq!(big.rgba[(50 * 200 + 150) * 4 + 3], 0);
+        // Cached
+        assert!(Rc::ptr_eq(&big, &logo.scaled(200, 100)));
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn scaling_keeps_colours_at_transparent_edges() {
+        // Opaque white next to transparent black
+        let bitmap = Bitmap { width: 2, height: 1, rgba: vec![255, 255, 255, 255, 0, 0, 0, 0] };
+        let scaled = bitmap.resize(4, 1);
+        for pixel in scaled.rgba.chunks_exact(4).filter(|p| p[3] > 0) {
+            assert_eq!(&pixel[..3], [255, 255, 255]);
+        }
+        assert!(scaled.rgba.chunks_exact(4).any(|p| p[3] > 0 && p[3] < 255));
+
+        // Shrinking averages all source pixels
+        let stripes = Bitmap {
+            width: 8,
+            height: 8,
+            rgba: (0..64).flat_map(|i| if i % 2 == 0 { [255; 4] } else { [0, 0, 0, 255] }).collect(),
+        };
+        let small = stripes.resize(2, 2);
+        assert!(small.rgba.chunks_exact(4).all(|p| (120..=135).contains(&p[0]) && p[3] == 255));
+    }
+
+    #[test]
+    fn broken_logos_are_rejected() {
+        let svg = temp_file("broken.svg", b"<svg");
+        assert!(Logo::load(&svg, None).is_err());
+        let gif = temp_file("logo.gif", b"GIF89a");
+        assert!(Logo::load(&gif, None).unwrap_err().to_string().contains("not a PNG or SVG"));
+        let empty = temp_file("empty.rgba", b"");
+        assert!(Logo::load(&empty, Some((0, 0))).is_err());
+        for path in [svg, gif, empty] {
+            std::fs::remove_file(&path).unwrap();
+        }
+    }
+}
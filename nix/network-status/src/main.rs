@@ -1,36 +1,139 @@
 use std::fs::{OpenOptions, File};
-use std::io;
-use std::path::Path;
-use std::os::unix::io::AsRawFd;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::os::unix::fs::MetadataExt;
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::process::ExitCode;
+use std::time::Duration;
 use qrcode::QrCode;
-use font8x8::{UnicodeFonts, BASIC_FONTS};
 
 #[cfg(feature = "image-output")]
 use image::{RgbImage, ImageBuffer, Rgb};
 
-// Embed the logo bitmap generated at build time
-const LOGO_WIDTH: usize = 223;
-const LOGO_HEIGHT: usize = 89;
-const LOGO_DATA: &[u8] = include_bytes!(concat!(env!("OUT_DIR"), "/clan-logo.rgba"));
-
-#[derive(Clone, Copy, Debug)]
+mod ansi;
+mod cli;
+mod config;
+mod damage;
+mod drm;
+mod font;
+mod json;
+mod layout;
+mod logo;
+mod login;
+mod netlink;
+mod outputs;
+mod pixel;
+mod rotation;
+mod ssh;
+mod text;
+mod theme;
+mod uevent;
+mod vt;
+mod watch;
+
+use cli::{Cli, CliError, Command};
+use config::Config;
+use font::Font;
+use netlink::{Family, Interface, OperState};
+use outputs::Outputs;
+use pixel::{Channel, PixelFormat};
+use rotation::Rotation;
+use ssh::HostKey;
+use logo::Logo;
+use theme::Theme;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 struct Color {
     r: u8,
     g: u8,
     b: u8,
 }
 
+impl Color {
+    /// `self` drawn over `background` with `alpha` opacity
+    fn mix(self, background: Color, alpha: u8) -> Color {
+        let channel = |fg: u8, bg: u8| {
+            ((fg as u32 * alpha as u32 + bg as u32 * (255 - alpha as u32) + 127) / 255) as u8
+        };
+        Color {
+            r: channel(self.r, background.r),
+            g: channel(self.g, background.g),
+            b: channel(self.b, background.b),
+        }
+    }
+}
+
 struct FramebufferConfig {
     width: usize,          // Visible width (xres)
     height: usize,         // Visible height (yres)
-    stride: usize,         // Line stride in pixels (xres_virtual)
-    bytes_per_pixel: usize,
-    red_offset: usize,
-    green_offset: usize,
-    blue_offset: usize,
+    stride: usize,         // Bytes per line, including padding (line_length)
+    dpi: Option<u32>,      // From the physical size, if the display reports it
+    format: PixelFormat,
+    rotation: Rotation,    // Of the picture drawn with put_pixel and blend_pixel
+}
+
+/// Pixels per inch of a display `pixels` wide and `mm` millimetres wide
+fn dpi(pixels: u32, mm: u32) -> Option<u32> {
+    // Projectors and some VMs report nonsense like 1mm or 0
+    if mm < 10 {
+        return None;
+    }
+    Some((pixels as u64 * 254 / (mm as u64 * 10)) as u32)
+}
+
+impl FramebufferConfig {
+    /// Where the pixel at `(x, y)` starts in the buffer
+    fn offset(&self, x: usize, y: usize) -> usize {
+        y * self.stride + x * self.format.bytes_per_pixel
+    }
+
+    /// Size of the picture, the framebuffer turned by `rotation`
+    fn screen_size(&self) -> (usize, usize) {
+        self.rotation.screen_size(self.width, self.height)
+    }
+
+    /// Where the picture's pixel at `(x, y)` starts, `None` outside of it
+    fn picture_offset(&self, x: usize, y: usize) -> Option<usize> {
+        let (width, height) = self.screen_size();
+        if x >= width || y >= height {
+            return None;
+        }
+        let (x, y) = self.rotation.to_framebuffer(x, y, self.width, self.height);
+        Some(self.offset(x, y))
+    }
+
+    /// Set one pixel of the picture, coordinates outside of it are ignored
+    fn put_pixel(&self, buffer: &mut [u8], x: usize, y: usize, color: Color) {
+        if let Some(offset) = self.picture_offset(x, y) {
+            self.format.write(&mut buffer[offset..], color);
+        }
+    }
+
+    /// Mix `color` into a pixel, `alpha` 255 replaces it
+    fn blend_pixel(&self, buffer: &mut [u8], x: usize, y: usize, color: Color, alpha: u8) {
+        let Some(offset) = self.picture_offset(x, y).filter(|_| alpha > 0) else {
+            return;
+        };
+        let pixel = &mut buffer[offset..];
+        let color = if alpha == 255 { color } else { color.mix(self.format.read(pixel), alpha) };
+        self.format.write(pixel, color);
+    }
+
+    /// Set every pixel of `buffer`, including line padding
+    fn fill(&self, buffer: &mut [u8], color: Color) {
+        for pixel in buffer.chunks_exact_mut(self.format.bytes_per_pixel) {
+            self.format.write(pixel, color);
+        }
+    }
 }
 
-const FB_PATH: &str = "/dev/fb0";
+/// Shown (and reported as `null` in JSON) until the data source exists
+const WAITING_FOR_PASSWORD: &str = "(waiting...)";
+const WAITING_FOR_TOR: &str = "(waiting for tor...)";
+
+/// Safety net re-read in case a notification got lost
+const FALLBACK_POLL_INTERVAL: Duration = Duration::from_secs(60);
 
 #[repr(C)]
 #[derive(Default)]
@@ -66,6 +169,115 @@ struct FbVarScreeninfo {
     reserved: [u32; 4],
 }
 
+#[repr(C)]
+#[derive(Default)]
+struct FbFixScreeninfo {
+    id: [u8; 16],
+    smem_start: libc::c_ulong,
+    smem_len: u32,
+    type_: u32,
+    type_aux: u32,
+    visual: u32,
+    xpanstep: u16,
+    ypanstep: u16,
+    ywrapstep: u16,
+    line_length: u32,
+    mmio_start: libc::c_ulong,
+    mmio_len: u32,
+    accel: u32,
+    capabilities: u16,
+    reserved: [u16; 2],
+}
+
+/// From `<linux/fb.h>`
+const FBIOGET_VSCREENINFO: u32 = 0x4600;
+const FBIOGET_FSCREENINFO: u32 = 0x4602;
+
+/// Read the variable and fixed screen info of a framebuffer device
+fn read_screeninfo(fd: RawFd) -> io::Result<(FbVarScreeninfo, FbFixScreeninfo)> {
+    let mut vinfo = FbVarScreeninfo::default();
+    let mut finfo = FbFixScreeninfo::default();
+    unsafe {
+        if libc::ioctl(fd, FBIOGET_VSCREENINFO as _, &mut vinfo) < 0
+            || libc::ioctl(fd, FBIOGET_FSCREENINFO as _, &mut finfo) < 0
+        {
+            return Err(io::Error::last_os_error());
+        }
+    }
+    Ok((vinfo, finfo))
+}
+
+/// What the mapping and the layout of a framebuffer depend on
+///
+/// The driver changing any of it, e.g. a new resolution or a KMS driver
+/// replacing efifb, means the device has to be opened again.
+#[derive(Debug, PartialEq, Eq)]
+struct FbMode {
+    xres: u32,
+    yres: u32,
+    format: PixelFormat,
+    rotate: u32,
+    line_length: u32,
+    smem_len: u32,
+}
+
+impl FbMode {
+    fn new(vinfo: &FbVarScreeninfo, finfo: &FbFixScreeninfo) -> Self {
+        FbMode {
+            xres: vinfo.xres,
+            yres: vinfo.yres,
+            format: vinfo.pixel_format(),
+            rotate: vinfo.rotate,
+            line_length: finfo.line_length,
+            smem_len: finfo.smem_len,
+        }
+    }
+}
+
+/// Where the visible screen is in a framebuffer's memory
+#[derive(Debug, PartialEq, Eq)]
+struct VisibleWindow {
+    /// Bytes per line
+    stride: usize,
+    /// Byte offset of the top left pixel, moved by panning
+    origin: usize,
+    /// Bytes to map, the whole framebuffer memory
+    memory: usize,
+}
+
+/// The visible window from the pan offsets and `line_length`, checked
+/// against the framebuffer memory
+///
+/// Drivers that leave `line_length` or `smem_len` at 0 get them derived from
+/// the virtual resolution.
+fn visible_window(vinfo: &FbVarScreeninfo, finfo: &FbFixScreeninfo) -> Result<VisibleWindow, String> {
+    let bytes_per_pixel = (vinfo.bits_per_pixel as usize).div_ceil(8);
+    let stride = match finfo.line_length {
+        0 => vinfo.xres_virtual as usize * bytes_per_pixel,
+        line_length => line_length as usize,
+    };
+    let memory = match finfo.smem_len {
+        0 => stride * vinfo.yres_virtual as usize,
+        smem_len => smem_len as usize,
+    };
+    let window = VisibleWindow {
+        stride,
+        origin: vinfo.yoffset as usize * stride + vinfo.xoffset as usize * bytes_per_pixel,
+        memory,
+    };
+
+    let line = vinfo.xres as usize * bytes_per_pixel;
+    if line > stride {
+        return Err(format!("a visible line is {} bytes, but line_length is {}", line, stride));
+    }
+    let end = window.origin + stride * (vinfo.yres as usize).saturating_sub(1) + line;
+    if end > memory {
+        let message = format!("the visible screen ends at byte {}, but smem_len is {}", end, memory);
+        return Err(message);
+    }
+    Ok(window)
+}
+
 #[repr(C)]
 #[derive(Default)]
 struct FbBitfield {
@@ -74,6 +286,46 @@ struct FbBitfield {
     msb_right: u32,
 }
 
+impl FbBitfield {
+    fn channel(&self) -> Channel {
+        Channel {
+            offset: self.offset,
+            length: self.length,
+            msb_right: self.msb_right != 0,
+        }
+    }
+}
+
+impl FbVarScreeninfo {
+    fn pixel_format(&self) -> PixelFormat {
+        PixelFormat {
+            bytes_per_pixel: (self.bits_per_pixel as usize).div_ceil(8),
+            red: self.red.channel(),
+            green: self.green.channel(),
+            blue: self.blue.channel(),
+            alpha: self.transp.channel(),
+        }
+    }
+
+    /// Name of the pixel layout, in memory byte order of a little endian pixel
+    fn format_name(&self) -> &'static str {
+        let offsets = (self.red.offset, self.green.offset, self.blue.offset);
+        let has_alpha = self.transp.length > 0;
+        match (self.bits_per_pixel, offsets, self.green.length) {
+            (32, (16, 8, 0), _) if has_alpha && self.transp.offset == 24 => "BGRA",
+            (32, (0, 8, 16), _) if has_alpha && self.transp.offset == 24 => "RGBA",
+            (32, (24, 16, 8), _) if has_alpha && self.transp.offset == 0 => "ABGR",
+            (32, (8, 16, 24), _) if has_alpha && self.transp.offset == 0 => "ARGB",
+            (24 | 32, (0, 8, 16), _) => "RGB",
+            (24 | 32, (16, 8, 0), _) => "BGR",
+            (16, (11, 5, 0), 6) => "RGB565",
+            (16, (0, 5, 11), 6) => "BGR565",
+            (15 | 16, (10, 5, 0), 5) => "RGB555",
+            _ => "Custom/Unknown",
+        }
+    }
+}
+
 /// Safe RAII wrapper for memory-mapped framebuffer
 /// Automatically calls munmap on drop
 struct FramebufferMap {
@@ -81,43 +333,125 @@ struct FramebufferMap {
     size: usize,
 }
 
+/// Memory the display is drawn into
+enum Surface {
+    /// `origin` is where the visible screen starts in `map`, `mode` and
+    /// `inode` (of the device node) are from when the device was opened
+    Fbdev { path: PathBuf, fb: File, map: FramebufferMap, origin: usize, mode: FbMode, inode: u64 },
+    Drm(drm::Output),
+}
+
+impl Surface {
+    /// The visible screen and whatever follows it
+    fn as_slice_mut(&mut self) -> &mut [u8] {
+        match self {
+            Surface::Fbdev { map, origin, .. } => &mut map.as_slice_mut()[*origin..],
+            Surface::Drm(output) => output.as_slice_mut(),
+        }
+    }
+
+    /// Whether the device changed under us and has to be opened again
+    ///
+    /// An fbdev whose driver went away (e.g. efifb, once the GPU driver
+    /// loads) fails the ioctls, its replacement gets a new device node.
+    fn is_stale(&self) -> bool {
+        let Surface::Fbdev { path, fb, mode, inode, .. } = self else {
+            return false;
+        };
+        let replaced = std::fs::metadata(path).map_or(true, |m| m.ino() != *inode);
+        replaced || read_screeninfo(fb.as_raw_fd()).map_or(true, |(vinfo, finfo)| FbMode::new(&vinfo, &finfo) != *mode)
+    }
+
+    /// Pick up the console panning the framebuffer, true if the visible
+    /// screen moved and has to be redrawn
+    fn follow_pan(&mut self) -> bool {
+        let Surface::Fbdev { fb, origin, .. } = self else {
+            return false;
+        };
+        let window = read_screeninfo(fb.as_raw_fd()).ok().and_then(|(vinfo, finfo)| visible_window(&vinfo, &finfo).ok());
+        match window {
+            Some(window) if window.origin != *origin => {
+                *origin = window.origin;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Make sure the `damage` rectangles we drew end up on the screen
+    fn flush(&self, damage: &[layout::Rect], fb_config: &FramebufferConfig) {
+        match self {
+            Surface::Fbdev { map, origin, .. } => {
+                let top = damage.iter().map(|r| r.y).min().unwrap_or(0);
+                let bottom = damage.iter().map(|r| r.bottom()).max().unwrap_or(0);
+                let _ = map.sync(origin + top * fb_config.stride..origin + bottom * fb_config.stride);
+            }
+            Surface::Drm(output) => output.flush(damage),
+        }
+    }
+}
+
 struct FramebufferState {
-    _fb: File,
+    surface: Surface,
     config: FramebufferConfig,
-    map: FramebufferMap,
+    /// Drawn off screen, then the changes are copied to `surface`
+    frame: Vec<u8>,
+    /// What `surface` shows, empty if unknown
+    shown: Vec<u8>,
     qr_code: QrCode,
-    qr_size: usize,
-    qr_pixel_size: usize,
-    x_offset: usize,
-    y_offset: usize,
+    settings: Config,
+    font: Rc<Font>,
 }
 
 impl FramebufferState {
-    /// Update the QR code and recalculate layout if needed
-    fn update_qr_code(&mut self, login_json: &str) {
-        self.qr_code = QrCode::new(login_json)
-            .unwrap_or_else(|_| QrCode::new(r#"{"status": "waiting"}"#).unwrap());
+    fn new(
+        surface: Surface,
+        config: FramebufferConfig,
+        state: &DisplayState,
+        settings: &Config,
+        font: Rc<Font>,
+    ) -> Self {
+        let qr_code = login::qr_code(state);
+
+        FramebufferState {
+            surface,
+            config,
+            frame: Vec::new(),
+            shown: Vec::new(),
+            qr_code,
+            settings: settings.clone(),
+            font,
+        }
+    }
 
-        let layout = calculate_qr_layout(&self.config, &self.qr_code);
-        self.qr_size = layout.0;
-        self.qr_pixel_size = layout.1;
-        self.x_offset = layout.2;
-        self.y_offset = layout.3;
+    /// Replace the QR code, the layout follows its size on the next render
+    fn set_qr_code(&mut self, qr_code: QrCode) {
+        self.qr_code = qr_code;
     }
 
     /// Render the display state to the framebuffer
+    ///
+    /// Only what changed since the last frame is written to the device.
     fn render(&mut self, state: &DisplayState) {
-        render_display(
-            self.map.as_slice_mut(),
-            &self.config,
-            &self.qr_code,
-            self.qr_size,
-            self.qr_pixel_size,
-            self.x_offset,
-            self.y_offset,
-            state,
-        );
-        let _ = self.map.sync();
+        let size = self.config.stride * self.config.height;
+        self.frame.resize(size, 0);
+        render_display(&mut self.frame, &self.config, &self.qr_code, state, &self.settings, &self.font);
+
+        if self.surface.follow_pan() {
+            self.shown.clear();
+        }
+        let damage = damage::diff(&self.shown, &self.frame, &self.config);
+        if !damage.is_empty() {
+            damage::copy(&self.frame, self.surface.as_slice_mut(), &damage, &self.config);
+            self.surface.flush(&damage, &self.config);
+        }
+        std::mem::swap(&mut self.shown, &mut self.frame);
+    }
+
+    /// Render all of the screen again, for when something else drew on it
+    fn redraw(&mut self, state: &DisplayState) {
+        self.shown.clear();
+        self.render(state);
     }
 }
 
@@ -125,15 +459,16 @@ impl FramebufferMap {
     /// Create a new memory-mapped framebuffer
     ///
     /// # Safety
-    /// The file descriptor must be valid and represent a framebuffer device
-    unsafe fn new(fd: std::os::unix::io::RawFd, size: usize) -> io::Result<Self> {
+    /// The file descriptor must be valid and represent a framebuffer device or
+    /// DRM dumb buffer mapping
+    unsafe fn new(fd: std::os::unix::io::RawFd, size: usize, offset: libc::off_t) -> io::Result<Self> {
         let ptr = libc::mmap(
             std::ptr::null_mut(),
             size,
             libc::PROT_READ | libc::PROT_WRITE,
             libc::MAP_SHARED,  // MAP_SHARED ensures changes are visible to display controller
             fd,
-            0,
+            offset,
         );
 
         if ptr == libc::MAP_FAILED {
@@ -151,13 +486,20 @@ impl FramebufferMap {
         unsafe { std::slice::from_raw_parts_mut(self.ptr, self.size) }
     }
 
-    /// Sync the mapped memory to the device
+    /// Sync `range` of the mapped memory to the device
     /// This flushes CPU caches and ensures changes are visible to hardware
-    fn sync(&self) -> io::Result<()> {
+    fn sync(&self, range: std::ops::Range<usize>) -> io::Result<()> {
+        // msync wants a page aligned address
+        let page_size = unsafe { libc::sysconf(libc::_SC_PAGESIZE) }.max(1) as usize;
+        let start = range.start / page_size * page_size;
+        let end = range.end.min(self.size);
+        if start >= end {
+            return Ok(());
+        }
         let result = unsafe {
             libc::msync(
-                self.ptr as *mut libc::c_void,
-                self.size,
+                self.ptr.add(start) as *mut libc::c_void,
+                end - start,
                 libc::MS_SYNC,  // MS_SYNC blocks until data is written to device
             )
         };
@@ -207,53 +549,87 @@ fn is_serial_console() -> bool {
     result == 0
 }
 
-fn main() -> io::Result<()> {
-    let args: Vec<String> = std::env::args().collect();
+fn main() -> ExitCode {
+    let cli = match cli::parse(std::env::args_os().skip(1)) {
+        Ok(cli) => cli,
+        Err(e @ (CliError::Help | CliError::Version)) => {
+            println!("{}", e.to_string().trim_end());
+            return ExitCode::from(e.exit_code());
+        }
+        Err(e) => {
+            eprintln!("network-status: {}", e);
+            eprintln!("Try 'network-status --help' for more information.");
+            return ExitCode::from(e.exit_code());
+        }
+    };
 
-    // Check for --debug-fb argument
-    if args.len() > 1 && args[1] == "--debug-fb" {
-        return print_framebuffer_info();
+    match run(cli) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("network-status: {}", e);
+            ExitCode::FAILURE
+        }
     }
+}
 
-    // Check for --output-image argument
-    #[cfg(feature = "image-output")]
-    if args.len() > 1 && args[1] == "--output-image" {
-        let output_path = if args.len() > 2 {
-            args[2].clone()
-        } else {
-            "network-status.png".to_string()
-        };
-        return render_to_image(&output_path);
-    }
+fn run(cli: Cli) -> io::Result<()> {
+    let mut config = Config::load(cli.config.as_deref())
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, format!("failed to load config: {}", e)))?;
 
-    #[cfg(not(feature = "image-output"))]
-    if args.len() > 1 && args[1] == "--output-image" {
-        eprintln!("Error: image-output feature not enabled in this build");
-        eprintln!("Build with --features image-output to use this feature");
-        return Err(io::Error::new(io::ErrorKind::Other, "image-output feature not enabled"));
+    // Command line options take precedence over the config file
+    if let Some(fb_device) = cli.fb_device {
+        config.framebuffer = Some(fb_device);
+    }
+    if let Some(drm_device) = cli.drm_device {
+        config.drm_device = Some(drm_device);
+    }
+    if let Some(layout) = cli.layout {
+        config.layout = layout;
+    }
+    if let Some(rotate) = cli.rotate {
+        config.rotate = Some(rotate);
+    }
+    if let Some(theme) = cli.theme {
+        config.theme = Theme::load(&theme)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, format!("failed to load theme: {}", e)))?;
+    }
+    if let Some(interval) = cli.interval {
+        config.poll_interval = interval;
     }
 
-    display()
-}
-
-fn print_framebuffer_info() -> io::Result<()> {
-    if !Path::new(FB_PATH).exists() {
-        eprintln!("Error: Framebuffer device {} not found", FB_PATH);
-        return Err(io::Error::new(io::ErrorKind::NotFound, "framebuffer not found"));
+    match cli.command {
+        Command::Display => display(&config, &DisplayOptions {
+            once: cli.once,
+            framebuffer: !cli.no_framebuffer,
+            terminal: !cli.no_terminal,
+        }),
+        Command::Text => {
+            print_terminal_output(&DisplayState::read_current(&config), &config);
+            Ok(())
+        }
+        Command::Json => print_json(&config, cli.follow, cli.login),
+        Command::DebugFb => print_framebuffer_info(&config),
+        #[cfg(feature = "image-output")]
+        Command::Render { output } => render_to_image(&config, &output),
+        #[cfg(not(feature = "image-output"))]
+        Command::Render { .. } => Err(io::Error::other(
+            "render: image output is not supported by this build (build with --features image-output)",
+        )),
     }
+}
 
+fn print_framebuffer_info(config: &Config) -> io::Result<()> {
+    let path = config
+        .framebuffer
+        .clone()
+        .unwrap_or_else(|| PathBuf::from(outputs::DEFAULT_FRAMEBUFFER));
     let fb = OpenOptions::new()
         .read(true)
         .write(false)
-        .open(FB_PATH)?;
+        .open(&path)
+        .map_err(|e| io::Error::new(e.kind(), format!("{}: {}", path.display(), e)))?;
 
-    let mut vinfo: FbVarScreeninfo = Default::default();
-    unsafe {
-        let ret = libc::ioctl(fb.as_raw_fd(), 0x4600, &mut vinfo);
-        if ret < 0 {
-            return Err(io::Error::last_os_error());
-        }
-    }
+    let (vinfo, finfo) = read_screeninfo(fb.as_raw_fd())?;
 
     println!("=== Framebuffer Information ===");
     println!();
@@ -267,9 +643,18 @@ fn print_framebuffer_info() -> io::Result<()> {
     println!("  xoffset:                  {}", vinfo.xoffset);
     println!("  yoffset:                  {}", vinfo.yoffset);
     println!();
+    let id_len = finfo.id.iter().position(|&b| b == 0).unwrap_or(finfo.id.len());
+    println!("Fixed Screen Info:");
+    println!("  id (driver):              {}", String::from_utf8_lossy(&finfo.id[..id_len]));
+    println!("  smem_len:                 {} bytes", finfo.smem_len);
+    println!("  line_length:              {} bytes", finfo.line_length);
+    println!("  type / visual:            {} / {}", finfo.type_, finfo.visual);
+    println!("  xpanstep / ypanstep:      {} / {}", finfo.xpanstep, finfo.ypanstep);
+    println!("  ywrapstep:                {}", finfo.ywrapstep);
+    println!();
     println!("Color Configuration:");
     println!("  bits_per_pixel:           {}", vinfo.bits_per_pixel);
-    println!("  bytes_per_pixel:          {}", vinfo.bits_per_pixel / 8);
+    println!("  bytes_per_pixel:          {}", (vinfo.bits_per_pixel as usize).div_ceil(8));
     println!("  grayscale:                {}", vinfo.grayscale);
     println!();
     println!("Color Component Layout:");
@@ -283,19 +668,7 @@ fn print_framebuffer_info() -> io::Result<()> {
              vinfo.transp.offset, vinfo.transp.length, vinfo.transp.msb_right);
     println!();
 
-    // Determine pixel format
-    let format = if vinfo.red.offset == 0 && vinfo.green.offset == 8 && vinfo.blue.offset == 16 {
-        "RGB"
-    } else if vinfo.blue.offset == 0 && vinfo.green.offset == 8 && vinfo.red.offset == 16 {
-        "BGR"
-    } else if vinfo.red.offset == 16 && vinfo.green.offset == 8 && vinfo.blue.offset == 0 && vinfo.transp.offset == 24 {
-        "BGRA"
-    } else if vinfo.blue.offset == 16 && vinfo.green.offset == 8 && vinfo.red.offset == 0 && vinfo.transp.offset == 24 {
-        "RGBA"
-    } else {
-        "Custom/Unknown"
-    };
-    println!("  Detected format:          {}", format);
+    println!("  Detected format:          {}", vinfo.format_name());
     println!();
 
     println!("Timing (for reference):");
@@ -315,23 +688,62 @@ fn print_framebuffer_info() -> io::Result<()> {
     println!("  rotate:                   {} (0=normal, 1=90°, 2=180°, 3=270°)", vinfo.rotate);
     println!();
 
-    // Calculate memory requirements
-    let line_size = vinfo.xres_virtual * (vinfo.bits_per_pixel / 8);
-    let total_size = line_size * vinfo.yres_virtual;
+    let bytes_per_pixel = (vinfo.bits_per_pixel as usize).div_ceil(8);
+    let window = visible_window(&vinfo, &finfo);
     println!("Memory Layout:");
-    println!("  Line size (stride):       {} bytes", line_size);
-    println!("  Total framebuffer size:   {} bytes ({:.2} MB)",
-             total_size, total_size as f64 / 1024.0 / 1024.0);
+    match &window {
+        Ok(window) => {
+            println!("  Line size (stride):       {} bytes", window.stride);
+            println!("  Total framebuffer size:   {} bytes ({:.2} MB)",
+                     window.memory, window.memory as f64 / 1024.0 / 1024.0);
+            println!("  Visible screen starts at: byte {}", window.origin);
+
+            // Check for padding
+            let expected_line_size = vinfo.xres as usize * bytes_per_pixel;
+            if window.stride > expected_line_size {
+                println!("  ⚠ Line padding detected:  {} bytes per line", window.stride - expected_line_size);
+                println!("                            (must use line_length for addressing)");
+            } else {
+                println!("  ✓ No line padding detected");
+            }
+            if finfo.line_length != 0 && finfo.line_length as usize != vinfo.xres_virtual as usize * bytes_per_pixel {
+                println!("  ⚠ line_length is not xres_virtual * bytes_per_pixel, line_length is used");
+            }
+            if finfo.line_length == 0 || finfo.smem_len == 0 {
+                println!("  ⚠ The driver leaves line_length or smem_len at 0, derived from the virtual resolution");
+            }
+            println!("  ✓ The visible screen fits in the framebuffer memory");
+        }
+        Err(e) => println!("  ✗ {}", e),
+    }
+    println!();
 
-    // Check for padding
-    let expected_line_size = vinfo.xres * (vinfo.bits_per_pixel / 8);
-    if line_size > expected_line_size {
-        let padding = line_size - expected_line_size;
-        println!("  ⚠ Line padding detected:  {} bytes per line", padding);
-        println!("                            (xres_virtual > xres, must use stride for addressing)");
-    } else {
-        println!("  ✓ No line padding detected");
+    // What the status screen would look like with the current state
+    let state = DisplayState::read_current(config);
+    let code = login::qr_code(&state);
+    let sections = build_sections(&state, config);
+    let font = Font::load(&config.fonts)?;
+    let content = layout::Content {
+        sections: &sections,
+        qr_modules: code.width(),
+        font: &font,
+        font_size: config.font_size,
+        theme: &config.theme,
+    };
+    let dpi = dpi(vinfo.xres, vinfo.width);
+    let rotation = Rotation::pick(config.rotate, Rotation::from_kernel(vinfo.rotate).unwrap_or_default());
+    let (width, height) = rotation.screen_size(vinfo.xres as usize, vinfo.yres as usize);
+    let layout = layout::compute(width, height, dpi, config.layout, &content);
+    println!("Layout:");
+    println!("  Rotation:                 {}° clockwise ({}x{})", rotation.degrees(), width, height);
+    match dpi {
+        Some(dpi) => println!("  Physical width:           {} mm ({} DPI)", vinfo.width, dpi),
+        None => println!("  Physical width:           unknown"),
     }
+    println!("  Arrangement:              text {:?} QR code", layout.arrangement);
+    println!("  Text size:                {} pixels ({}x{} per character)",
+             layout.text_size, layout.char_width, layout.line_height);
+    println!("  QR module size:           {} pixels", layout.qr_scale);
 
     Ok(())
 }
@@ -340,169 +752,275 @@ struct DisplayState {
     root_password: String,
     onion_hostname: String,
     login_json: String,
-    ip_addrs: Vec<String>,
+    interfaces: Vec<Interface>,
     hostname: String,
+    host_keys: Vec<HostKey>,
+    ssh_port: u16,
 }
 
 impl DisplayState {
-    fn read_current() -> Self {
-        let root_password = std::fs::read_to_string("/var/shared/root-password")
-            .unwrap_or_else(|_| "(waiting...)".to_string())
+    fn read_current(config: &Config) -> Self {
+        let root_password = std::fs::read_to_string(&config.root_password_file)
+            .unwrap_or_else(|_| WAITING_FOR_PASSWORD.to_string())
             .trim()
             .to_string();
 
         // Read tor onion hostname directly from tor data directory
-        let onion_hostname = std::fs::read_to_string("/var/lib/tor/onion/hidden-ssh/hostname")
-            .unwrap_or_else(|_| "(waiting for tor...)".to_string())
+        let onion_hostname = std::fs::read_to_string(&config.onion_hostname_file)
+            .unwrap_or_else(|_| WAITING_FOR_TOR.to_string())
             .trim()
             .to_string();
 
-        let ip_addrs = get_ip_addresses();
-        let hostname = get_hostname();
-
-        // Generate login JSON in memory for QR code
-        let login_json = generate_login_json(&root_password, &onion_hostname, &ip_addrs);
+        let interfaces = get_interfaces();
+        let hostname = get_hostname(&config.hostname_file);
+        let host_keys = ssh::read_host_keys(&config.ssh_dir);
+        let ssh_port = ssh::read_ssh_port(&config.sshd_config_file);
 
-        DisplayState {
+        let mut state = DisplayState {
             root_password,
             onion_hostname,
-            login_json,
-            ip_addrs,
+            login_json: String::new(),
+            interfaces,
             hostname,
-        }
+            host_keys,
+            ssh_port,
+        };
+
+        // Generate login JSON in memory for QR code
+        state.login_json = login::generate_login_json(&state);
+        state
     }
 
     fn has_changed(&self, other: &DisplayState) -> bool {
         self.root_password != other.root_password
             || self.onion_hostname != other.onion_hostname
             || self.login_json != other.login_json
-            || self.ip_addrs != other.ip_addrs
+            || self.interfaces != other.interfaces
             || self.hostname != other.hostname
+            || self.host_keys != other.host_keys
+            || self.ssh_port != other.ssh_port
     }
 }
 
-fn calculate_qr_layout(fb_config: &FramebufferConfig, qr_code: &QrCode) -> (usize, usize, usize, usize) {
-    let qr_size = qr_code.width();
-    let quiet_zone = 4;
-    let qr_with_quiet = qr_size + (quiet_zone * 2);
+/// Map a framebuffer device in its current mode
+fn open_fbdev(path: &Path) -> io::Result<(Surface, FramebufferConfig)> {
+    let fb = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open(path)?;
+
+    let (vinfo, finfo) = read_screeninfo(fb.as_raw_fd())?;
+    let window = visible_window(&vinfo, &finfo).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+    let format = vinfo.pixel_format();
+    // A pixel is written as 1 to 4 whole bytes, several pixels per byte
+    // (monochrome, 4 bit palettes) are not supported
+    if vinfo.bits_per_pixel < 8 || format.bytes_per_pixel > 4 {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("{} bits per pixel are not supported", vinfo.bits_per_pixel),
+        ));
+    }
 
-    // Reserve space for text (approximately 400 pixels)
-    let available_height = fb_config.height.saturating_sub(400);
-    let scale = std::cmp::min(
-        fb_config.width / (qr_with_quiet * 2),
-        available_height / qr_with_quiet
-    ).max(1); // Ensure scale is at least 1
+    let fb_config = FramebufferConfig {
+        width: vinfo.xres as usize,
+        height: vinfo.yres as usize,
+        stride: window.stride,
+        dpi: dpi(vinfo.xres, vinfo.width),
+        format,
+        rotation: Rotation::from_kernel(vinfo.rotate).unwrap_or_default(),
+    };
 
-    let qr_pixel_size = qr_size * scale;
-    let quiet_zone_pixels = quiet_zone * scale;
-    let total_size = qr_pixel_size + (quiet_zone_pixels * 2);
-    let x_offset = (fb_config.width - total_size) / 2 + quiet_zone_pixels;
-    let y_offset = 80 + quiet_zone_pixels;
+    let map = unsafe { FramebufferMap::new(fb.as_raw_fd(), window.memory, 0)? };
+    let surface = Surface::Fbdev {
+        path: path.to_path_buf(),
+        mode: FbMode::new(&vinfo, &finfo),
+        inode: fb.metadata()?.ino(),
+        fb,
+        map,
+        origin: window.origin,
+    };
+    Ok((surface, fb_config))
+}
 
-    (qr_size, qr_pixel_size, x_offset, y_offset)
+/// Watch everything `DisplayState::read_current` reads
+///
+/// Returns None if notifications are unavailable, callers then fall back to
+/// polling. The framebuffer and DRM device directories are watched too so we
+/// notice outputs appearing and disappearing.
+fn create_watcher(config: &Config) -> Option<watch::Watcher> {
+    let framebuffer = config
+        .framebuffer
+        .clone()
+        .unwrap_or_else(|| PathBuf::from(outputs::DEFAULT_FRAMEBUFFER));
+    let drm_device = config
+        .drm_device
+        .clone()
+        .unwrap_or_else(|| Path::new(drm::DRI_DIR).join("card0"));
+    // The parent of a path is watched, so this covers every host key in there
+    let host_key = config.ssh_dir.join("ssh_host_ed25519_key.pub");
+    watch::Watcher::new(&[
+        config.root_password_file.as_path(),
+        config.onion_hostname_file.as_path(),
+        config.hostname_file.as_path(),
+        config.sshd_config_file.as_path(),
+        host_key.as_path(),
+        framebuffer.as_path(),
+        drm_device.as_path(),
+    ])
+    .map_err(|e| eprintln!("Change notifications unavailable, polling instead: {}", e))
+    .ok()
 }
 
-/// Try to open and initialize the framebuffer
-/// Returns None if framebuffer is not available or initialization fails
-fn open_framebuffer(state: &DisplayState) -> Option<FramebufferState> {
-    if !Path::new(FB_PATH).exists() {
-        return None;
+/// Block until something may have changed, `wake` became readable, or for
+/// one poll interval
+fn wait_for_changes(watcher: Option<&watch::Watcher>, wake: Option<RawFd>, config: &Config) {
+    let result = match watcher {
+        Some(watcher) => watcher.wait(FALLBACK_POLL_INTERVAL, wake),
+        None => watch::poll([wake.unwrap_or(-1)], config.poll_interval).map(|_| ()),
+    };
+    if let Err(e) = result {
+        eprintln!("Waiting for changes failed: {}", e);
+        std::thread::sleep(config.poll_interval);
     }
+}
 
-    (|| -> io::Result<FramebufferState> {
-        let fb = OpenOptions::new()
-            .read(true)
-            .write(true)
-            .open(FB_PATH)?;
-
-        let mut vinfo: FbVarScreeninfo = Default::default();
-        unsafe {
-            let ret = libc::ioctl(fb.as_raw_fd(), 0x4600, &mut vinfo);
-            if ret < 0 {
-                return Err(io::Error::last_os_error());
-            }
+/// Print the state as JSON, with `follow` as one line per change
+fn print_json(config: &Config, follow: bool, login_only: bool) -> io::Result<()> {
+    let format = |state: &DisplayState| {
+        if login_only {
+            state.login_json.clone()
+        } else {
+            json::state_json(state)
         }
+    };
 
-        let config = FramebufferConfig {
-            width: vinfo.xres as usize,
-            height: vinfo.yres as usize,
-            stride: vinfo.xres_virtual as usize,
-            bytes_per_pixel: (vinfo.bits_per_pixel / 8) as usize,
-            red_offset: (vinfo.red.offset / 8) as usize,
-            green_offset: (vinfo.green.offset / 8) as usize,
-            blue_offset: (vinfo.blue.offset / 8) as usize,
-        };
+    let mut current_state = DisplayState::read_current(config);
+    let mut stdout = io::stdout();
+    writeln!(stdout, "{}", format(&current_state))?;
+    if !follow {
+        return Ok(());
+    }
 
-        let screen_size = config.stride * config.height * config.bytes_per_pixel;
-        let map = unsafe { FramebufferMap::new(fb.as_raw_fd(), screen_size)? };
+    let watcher = create_watcher(config);
+    loop {
+        wait_for_changes(watcher.as_ref(), None, config);
 
-        let qr_code = QrCode::new(&state.login_json)
-            .unwrap_or_else(|_| QrCode::new(r#"{"status": "waiting"}"#).unwrap());
-        let (qr_size, qr_pixel_size, x_offset, y_offset) = calculate_qr_layout(&config, &qr_code);
+        let new_state = DisplayState::read_current(config);
+        if new_state.has_changed(&current_state) {
+            // Fails once the consumer closed the pipe, which ends the loop
+            writeln!(stdout, "{}", format(&new_state))?;
+            stdout.flush()?;
+            current_state = new_state;
+        }
+    }
+}
 
-        Ok(FramebufferState {
-            _fb: fb,
-            config,
-            map,
-            qr_code,
-            qr_size,
-            qr_pixel_size,
-            x_offset,
-            y_offset,
-        })
-    })().ok()
+/// Which outputs `display` may use and whether it keeps running
+struct DisplayOptions {
+    once: bool,
+    framebuffer: bool,
+    terminal: bool,
 }
 
-fn display() -> io::Result<()> {
-    let mut current_state = DisplayState::read_current();
+fn display(config: &Config, options: &DisplayOptions) -> io::Result<()> {
+    let mut current_state = DisplayState::read_current(config);
 
     // Determine if we're on a serial console (this won't change during runtime)
     let has_serial = is_serial_console();
 
-    // Try to initialize framebuffer immediately
-    let mut fb_state = open_framebuffer(&current_state);
-
-    // Initial render
-    if let Some(ref mut fb) = fb_state {
-        fb.render(&current_state);
+    // Open every screen that is available right now, this renders them
+    let font = if options.framebuffer { Font::load(&config.fonts)? } else { Font::embedded() };
+    // Dropped after the screens, so the console only gets the VT back once
+    // we stopped drawing
+    let mut console = if options.framebuffer && !options.once { vt::Console::open() } else { None };
+    let mut outputs = Outputs::new(Rc::new(font));
+    if options.framebuffer {
+        outputs.update(config, &current_state);
+        claim_console(console.as_mut(), &mut outputs, &current_state);
     }
 
     // Also render to terminal if on serial or no framebuffer available
-    if has_serial || fb_state.is_none() {
-        print_terminal_output(&current_state);
+    if options.terminal && (has_serial || outputs.is_empty()) {
+        print_terminal_output(&current_state, config);
+    }
+
+    if options.once {
+        if outputs.is_empty() && !options.terminal {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                "no DRM or framebuffer device is available",
+            ));
+        }
+        return Ok(());
     }
 
-    // Poll for changes and update all available outputs
+    let watcher = create_watcher(config);
+    let wake = console.as_ref().map(|console| console.as_raw_fd());
+
+    // Wait for changes and update all available outputs
     loop {
-        std::thread::sleep(std::time::Duration::from_secs(2));
-
-        // Try to initialize framebuffer if not already done and it becomes available
-        if fb_state.is_none() {
-            fb_state = open_framebuffer(&current_state);
-            if let Some(ref mut fb) = fb_state {
-                // Do initial framebuffer render
-                fb.render(&current_state);
+        wait_for_changes(watcher.as_ref(), wake, config);
+
+        if let Some(console) = &mut console {
+            for event in console.events() {
+                match event {
+                    vt::Event::Release => {
+                        // The screens are opened and drawn again on `Acquire`
+                        outputs.clear();
+                        console.release();
+                    }
+                    vt::Event::Acquire => console.acquire(),
+                    vt::Event::Quit => return Ok(()),
+                }
             }
         }
+        // Another VT is shown, we may not touch the screens
+        let active = console.as_ref().is_none_or(|console| console.is_active());
+
+        let new_state = DisplayState::read_current(config);
+        let changed = new_state.has_changed(&current_state);
+
+        // Pick up screens that were plugged in, removed or changed their mode
+        // first, so nothing is drawn with a stale geometry
+        let had_outputs = !outputs.is_empty();
+        if options.framebuffer && active {
+            outputs.update(config, &new_state);
+            claim_console(console.as_mut(), &mut outputs, &new_state);
+        }
 
-        let new_state = DisplayState::read_current();
-        if new_state.has_changed(&current_state) {
-            // Update framebuffer if available
-            if let Some(ref mut fb) = fb_state {
-                // Update QR code if login.json changed
-                if new_state.login_json != current_state.login_json {
-                    fb.update_qr_code(&new_state.login_json);
+        if changed {
+            // Update QR code if login.json changed
+            let qr_code = (new_state.login_json != current_state.login_json && !outputs.is_empty())
+                .then(|| login::qr_code(&new_state));
+            for fb in outputs.iter_mut() {
+                if let Some(qr_code) = &qr_code {
+                    fb.set_qr_code(qr_code.clone());
                 }
                 fb.render(&new_state);
             }
+        }
 
-            // Update terminal if on serial console or no framebuffer available
-            if has_serial || fb_state.is_none() {
-                print!("\x1B[2J\x1B[H"); // ANSI clear screen and move cursor to home
-                print_terminal_output(&new_state);
-            }
+        // Update terminal if on serial console or no screen is left
+        let lost_outputs = had_outputs && outputs.is_empty();
+        if options.terminal && active && (has_serial || outputs.is_empty()) && (changed || lost_outputs) {
+            print!("\x1B[2J\x1B[H"); // ANSI clear screen and move cursor to home
+            print_terminal_output(&new_state, config);
+        }
 
-            current_state = new_state;
+        current_state = new_state;
+    }
+}
+
+/// Keep fbcon off the screens while we show something on them, and give the
+/// console back to it once there is nothing left to show
+fn claim_console(console: Option<&mut vt::Console>, outputs: &mut Outputs, state: &DisplayState) {
+    let Some(console) = console else {
+        return;
+    };
+    // The text or cursor fbcon drew since we rendered is painted over
+    if console.set_graphics(!outputs.is_empty()) && !outputs.is_empty() {
+        for fb in outputs.iter_mut() {
+            fb.redraw(state);
         }
     }
 }
@@ -511,329 +1029,244 @@ fn render_display(
     buffer: &mut [u8],
     fb_config: &FramebufferConfig,
     code: &QrCode,
-    qr_size: usize,
-    qr_pixel_size: usize,
-    x_offset: usize,
-    y_offset: usize,
     state: &DisplayState,
+    settings: &Config,
+    font: &Font,
 ) {
-    // Clear buffer (black background)
-    buffer.fill(0);
-
-    let scale = qr_pixel_size / qr_size;
-    let quiet_zone = 4;
-    let quiet_zone_pixels = quiet_zone * scale;
-    let total_size = qr_pixel_size + (quiet_zone_pixels * 2);
-
-    // Draw white quiet zone (background for QR code)
-    let qz_start_x = x_offset - quiet_zone_pixels;
-    let qz_start_y = y_offset - quiet_zone_pixels;
-    for qz_y in 0..total_size {
-        for qz_x in 0..total_size {
-            let px = qz_start_x + qz_x;
-            let py = qz_start_y + qz_y;
-
-            if px < fb_config.width && py < fb_config.height {
-                let offset = (py * fb_config.stride + px) * fb_config.bytes_per_pixel;
-                buffer[offset + fb_config.red_offset] = 0xFF;
-                buffer[offset + fb_config.green_offset] = 0xFF;
-                buffer[offset + fb_config.blue_offset] = 0xFF;
-                if fb_config.bytes_per_pixel > 3 {
-                    buffer[offset + 3] = 0xFF;
+    let white = Color { r: 0xFF, g: 0xFF, b: 0xFF };
+
+    let theme = &settings.theme;
+    let sections = build_sections(state, settings);
+    let content = layout::Content {
+        sections: &sections,
+        qr_modules: code.width(),
+        font,
+        font_size: settings.font_size,
+        theme,
+    };
+    let (width, height) = fb_config.screen_size();
+    let layout = layout::compute(width, height, fb_config.dpi, settings.layout, &content);
+
+    fb_config.fill(buffer, theme.background);
+
+    // QR code on white, including the quiet zone
+    if layout.qr_scale > 0 {
+        fill_rect(buffer, fb_config, &layout.qr, white);
+        let scale = layout.qr_scale;
+        let origin = layout::QUIET_ZONE * scale;
+        for y in 0..code.width() {
+            for x in 0..code.width() {
+                if code[(x, y)] == qrcode::Color::Dark {
+                    let module = layout::Rect {
+                        x: layout.qr.x + origin + x * scale,
+                        y: layout.qr.y + origin + y * scale,
+                        width: scale,
+                        height: scale,
+                    };
+                    fill_rect(buffer, fb_config, &module, Color { r: 0, g: 0, b: 0 });
                 }
             }
         }
     }
 
-    // Draw QR code on white background
-    for y in 0..qr_size {
-        for x in 0..qr_size {
-            let module = code[(x, y)];
-            let color = if module == qrcode::Color::Dark { 0x00 } else { 0xFF };
-
-            // Draw scaled pixel
-            for dy in 0..scale {
-                for dx in 0..scale {
-                    let px = x_offset + (x * scale) + dx;
-                    let py = y_offset + (y * scale) + dy;
-
-                    if px < fb_config.width && py < fb_config.height {
-                        let offset = (py * fb_config.stride + px) * fb_config.bytes_per_pixel;
-                        buffer[offset + fb_config.red_offset] = color;
-                        buffer[offset + fb_config.green_offset] = color;
-                        buffer[offset + fb_config.blue_offset] = color;
-                        if fb_config.bytes_per_pixel > 3 {
-                            buffer[offset + 3] = 0xFF;
-                        }
-                    }
-                }
-            }
+    // Logo in the top-left corner as branding
+    if let (Some(logo), Some(rect), Some(background)) = (&theme.logo, layout.logo, layout.logo_background) {
+        if let Some(color) = theme.logo_background {
+            fill_rect(buffer, fb_config, &background, color);
         }
+        draw_logo(buffer, fb_config, logo, &rect);
     }
 
-    // Draw logo in top-left corner as branding
-    let logo_x = 30;
-    let logo_y = 30;
-    draw_logo(buffer, fb_config, logo_x, logo_y);
-
-    // Draw text information below QR code with better styling
-    let text_y_start = y_offset + qr_pixel_size + 50;
-    let line_height = 22;
-    let section_spacing = 30;
-    let left_margin = 50;
-    let indent = 70;
-
-    // Section 1: Login Credentials
-    draw_text(buffer, fb_config,
-              "Login Credentials", left_margin, text_y_start);
-
-    draw_text(buffer, fb_config,
-              &format!("  Root password: {}", state.root_password), left_margin, text_y_start + section_spacing);
-
-    // Section 2: Network Information
-    let network_section_y = text_y_start + section_spacing * 2;
-    draw_text(buffer, fb_config,
-              "Network Information", left_margin, network_section_y);
-
-    // Draw IP addresses with colors
-    let mut line_offset = 1;
-    for addr in state.ip_addrs.iter() {
-        let lines_used = draw_colored_line(buffer, fb_config,
-                         addr, indent, network_section_y + section_spacing + line_height * line_offset);
-        line_offset += lines_used;
+    for row in &layout.rows {
+        draw_row(buffer, fb_config, font, &layout, row, theme.background);
     }
 
-    // Section 3: Remote Access
-    let remote_section_y = network_section_y + section_spacing + line_height * line_offset + 10;
-    draw_text(buffer, fb_config,
-              "Remote Access", left_margin, remote_section_y);
-
-    draw_text(buffer, fb_config,
-              &format!("  Tor Hidden Service: {}", state.onion_hostname),
-              left_margin, remote_section_y + section_spacing);
-    draw_text(buffer, fb_config,
-              &format!("  Multicast DNS: {}.local", state.hostname),
-              left_margin, remote_section_y + section_spacing + line_height);
+    fill_rect(buffer, fb_config, &layout.separator, theme.separator);
+}
 
-    // Footer
-    let footer_y = remote_section_y + section_spacing + line_height * 2 + 20;
-    draw_separator_line(buffer, fb_config, left_margin, footer_y, fb_config.width - 100);
-    draw_text(buffer, fb_config,
-              "Press 'Ctrl-C' for console access",
-              left_margin, footer_y + 20);
+/// The enabled sections with their current content, in display order
+fn build_sections(state: &DisplayState, config: &Config) -> Vec<layout::Section> {
+    let (sections, titles) = (&config.sections, &config.theme.titles);
+    let mut result = Vec::new();
+    if sections.credentials {
+        result.push(layout::Section {
+            title: titles.credentials.clone(),
+            lines: vec![format!("Root password: {}", state.root_password)],
+        });
+    }
+    if sections.network {
+        result.push(layout::Section {
+            title: titles.network.clone(),
+            lines: state.interfaces.iter().map(format_interface_line).collect(),
+        });
+    }
+    if sections.remote {
+        result.push(layout::Section {
+            title: titles.remote.clone(),
+            lines: vec![
+                format!("Tor Hidden Service: {}", state.onion_hostname),
+                format!("Multicast DNS: {}.local", state.hostname),
+            ],
+        });
+    }
+    if sections.host_keys {
+        result.push(layout::Section {
+            title: titles.host_keys.clone(),
+            lines: format_host_key_lines(&state.host_keys),
+        });
+    }
+    result
 }
 
-fn print_terminal_output(state: &DisplayState) {
-    println!("Login Credentials");
-    println!("  Root password: {}", state.root_password);
-    println!();
-    println!("Network Information");
-    for addr in &state.ip_addrs {
-        println!("  {}", addr);
+fn print_terminal_output(state: &DisplayState, config: &Config) {
+    for section in build_sections(state, config) {
+        println!("{}", section.title);
+        for line in &section.lines {
+            println!("  {}", line);
+        }
+        println!();
     }
-    println!();
-    println!("Remote Access");
-    println!("  Tor Hidden Service: {}", state.onion_hostname);
-    println!("  Multicast DNS: {}.local", state.hostname);
-    println!();
     println!("{}",  "─".repeat(80));
-    println!("Press 'Ctrl-C' for console access");
-}
-
-fn print_network_addresses() {
-    if let Ok(output) = std::process::Command::new("ip")
-        .args(["-brief", "-color", "addr"])
-        .output()
-    {
-        if let Ok(stdout) = String::from_utf8(output.stdout) {
-            for line in stdout.lines() {
-                if !line.contains("127.0.0.1") {
-                    println!("{}", line);
-                }
-            }
-        }
+    println!("{}", config.theme.footer);
+}
+
+/// One `<TYPE>: SHA256:...` line per host key, or a placeholder if sshd
+/// hasn't generated any yet
+fn format_host_key_lines(host_keys: &[HostKey]) -> Vec<String> {
+    if host_keys.is_empty() {
+        return vec!["(waiting for sshd...)".to_string()];
     }
+    host_keys
+        .iter()
+        .map(|key| format!("{:<8} {}", format!("{}:", key.short_name()), key.fingerprint))
+        .collect()
 }
 
-fn get_hostname() -> String {
-    std::fs::read_to_string("/etc/hostname")
+fn get_hostname(path: &Path) -> String {
+    std::fs::read_to_string(path)
         .unwrap_or_else(|_| "nixos".to_string())
         .trim()
         .to_string()
 }
 
-fn escape_json_string(s: &str) -> String {
-    s.replace('\\', "\\\\")
-        .replace('"', "\\\"")
-        .replace('\n', "\\n")
-        .replace('\r', "\\r")
-        .replace('\t', "\\t")
+/// Interfaces that are up, excluding loopback
+fn get_interfaces() -> Vec<Interface> {
+    let mut interfaces = netlink::list_interfaces().unwrap_or_default();
+    interfaces.retain(|i| i.operstate == OperState::Up && !i.is_loopback());
+    interfaces
 }
 
-fn generate_login_json(password: &str, onion: &str, ip_addrs: &[String]) -> String {
-    // Extract just IP addresses (not the full line with interface name)
-    let addrs: Vec<String> = ip_addrs
-        .iter()
-        .flat_map(|line| {
-            // Parse lines like: "wlp192s0 UP 192.168.1.1/24 2001:db8::1/64"
-            let parts: Vec<&str> = line.split_whitespace().collect();
-            parts.into_iter()
-                .skip(2) // Skip interface name and status
-                .filter(|p| p.contains('.') || p.contains(':')) // Only IP addresses
-                .map(|addr| {
-                    // Remove CIDR suffix
-                    addr.split('/').next().unwrap_or(addr).to_string()
-                })
-                .collect::<Vec<_>>()
-        })
-        .collect();
+/// Format an interface like `ip -brief -color addr`, plus the MAC address
+fn format_interface_line(interface: &Interface) -> String {
+    let state_color = match interface.operstate {
+        OperState::Up => "\x1b[32m",
+        OperState::Down | OperState::LowerLayerDown => "\x1b[31m",
+        _ => "\x1b[0m",
+    };
 
-    // Generate JSON manually
-    let mut json = String::from("{");
-    json.push_str(&format!("\"pass\":\"{}\",", escape_json_string(password)));
-    json.push_str(&format!("\"tor\":\"{}\",", escape_json_string(onion)));
-    json.push_str("\"addrs\":[");
+    let mut line = format!(
+        "\x1b[36m{:<16}\x1b[0m {}{:<14}\x1b[0m ",
+        interface.name,
+        state_color,
+        interface.operstate.as_str()
+    );
 
-    for (i, addr) in addrs.iter().enumerate() {
-        if i > 0 {
-            json.push(',');
-        }
-        json.push_str(&format!("\"{}\"", escape_json_string(addr)));
+    if !interface.mac.is_empty() {
+        line.push_str(&format!("\x1b[33m{}\x1b[0m ", interface.mac_string()));
     }
 
-    json.push_str("]}");
-    json
-}
-
-fn get_ip_addresses() -> Vec<String> {
-    let mut addrs = Vec::new();
-    if let Ok(output) = std::process::Command::new("ip")
-        .args(["-brief", "-color", "addr"])
-        .output()
-    {
-        if let Ok(stdout) = String::from_utf8(output.stdout) {
-            for line in stdout.lines() {
-                if !line.contains("127.0.0.1") && line.contains("UP") {
-                    addrs.push(line.to_string());
-                }
-            }
-        }
+    for addr in &interface.addrs {
+        let color = match addr.family {
+            Family::Inet => "\x1b[35m",
+            Family::Inet6 => "\x1b[34m",
+        };
+        line.push_str(&format!("{}{}\x1b[0m/{} ", color, addr.addr, addr.prefix_len));
     }
-    addrs
+
+    line.trim_end().to_string()
 }
 
+/// A run of text drawn the same way
+#[derive(Clone, Debug, PartialEq)]
 struct TextSegment {
     text: String,
     color: Color,
+    /// `None` leaves the screen background
+    background: Option<Color>,
+    bold: bool,
+    underline: bool,
+    /// Swap the foreground and background colours
+    inverse: bool,
 }
 
-fn parse_colored_text(text: &str) -> Vec<TextSegment> {
-    let mut segments = Vec::new();
-    let mut current_text = String::new();
-    let mut current_color = Color { r: 255, g: 255, b: 255 }; // White default
-    let mut chars = text.chars().peekable();
-
-    while let Some(ch) = chars.next() {
-        if ch == '\x1b' && chars.peek() == Some(&'[') {
-            // Save current segment if there's text
-            if !current_text.is_empty() {
-                segments.push(TextSegment {
-                    text: current_text.clone(),
-                    color: current_color,
-                });
-                current_text.clear();
-            }
-
-            chars.next(); // consume '['
-            let mut code = String::new();
-
-            // Read until 'm'
-            while let Some(c) = chars.next() {
-                if c == 'm' {
-                    break;
-                }
-                code.push(c);
-            }
+impl TextSegment {
+    fn new(text: impl Into<String>, color: Color) -> Self {
+        TextSegment {
+            text: text.into(),
+            color,
+            background: None,
+            bold: false,
+            underline: false,
+            inverse: false,
+        }
+    }
 
-            // Parse color code
-            current_color = match code.as_str() {
-                "0" | "00" => Color { r: 255, g: 255, b: 255 }, // Reset to white
-                "1" | "01" => Color { r: 255, g: 255, b: 255 }, // Bold (keep current)
-                "30" => Color { r: 0, g: 0, b: 0 },              // Black
-                "31" | "1;31" => Color { r: 255, g: 85, b: 85 }, // Red
-                "32" | "1;32" => Color { r: 85, g: 255, b: 85 }, // Green
-                "33" | "1;33" => Color { r: 255, g: 255, b: 85 }, // Yellow
-                "34" | "1;34" => Color { r: 85, g: 85, b: 255 }, // Blue
-                "35" | "1;35" => Color { r: 255, g: 85, b: 255 }, // Magenta
-                "36" | "1;36" => Color { r: 85, g: 255, b: 255 }, // Cyan
-                "37" | "1;37" => Color { r: 255, g: 255, b: 255 }, // White
-                _ => current_color, // Keep current color for unknown codes
-            };
-        } else {
-            current_text.push(ch);
+    /// The same attributes with other text
+    fn with_text(&self, text: impl Into<String>) -> Self {
+        TextSegment {
+            text: text.into(),
+            ..*self
         }
     }
 
-    // Add final segment
-    if !current_text.is_empty() {
-        segments.push(TextSegment {
-            text: current_text,
-            color: current_color,
-        });
+    fn same_style(&self, other: &TextSegment) -> bool {
+        self.with_text(String::new()) == other.with_text(String::new())
     }
 
-    segments
+    /// Foreground and background as drawn on `screen`, i.e. with `inverse`
+    /// applied
+    fn colors(&self, screen: Color) -> (Color, Option<Color>) {
+        if self.inverse {
+            (self.background.unwrap_or(screen), Some(self.color))
+        } else {
+            (self.color, self.background)
+        }
+    }
 }
 
 #[cfg(feature = "image-output")]
-fn render_to_image(output_path: &str) -> io::Result<()> {
-    let state = DisplayState::read_current();
+fn render_to_image(config: &Config, output_path: &Path) -> io::Result<()> {
+    let state = DisplayState::read_current(config);
 
-    // Generate QR code (use placeholder if not available yet)
-    let code = QrCode::new(&state.login_json)
-        .unwrap_or_else(|_| QrCode::new(r#"{"status": "waiting"}"#).unwrap());
-    let qr_size = code.width();
+    let code = login::qr_code(&state);
 
     // Image dimensions - use BGR format like typical framebuffers
     let fb_config = FramebufferConfig {
         width: 1920,
         height: 1080,
-        stride: 1920,     // No padding for generated images
-        bytes_per_pixel: 4,
-        red_offset: 2,    // BGR format: Red at offset 2
-        green_offset: 1,  // Green at offset 1
-        blue_offset: 0,   // Blue at offset 0
+        stride: 1920 * 4, // No padding for generated images
+        dpi: None,
+        format: PixelFormat {
+            bytes_per_pixel: 4,
+            red: Channel::new(16, 8),   // BGR format: Red at byte 2
+            green: Channel::new(8, 8),  // Green at byte 1
+            blue: Channel::new(0, 8),   // Blue at byte 0
+            alpha: Channel::new(24, 8), // Alpha at byte 3
+        },
+        // As it would end up in the framebuffer of a rotated panel
+        rotation: config.rotate.unwrap_or_default(),
     };
 
-    // Calculate QR code scaling with quiet zone (4 modules on each side per spec)
-    let quiet_zone = 4;
-    let qr_with_quiet = qr_size + (quiet_zone * 2);
-
-    // Reserve space for text (approximately 400 pixels)
-    let available_height = fb_config.height.saturating_sub(400);
-    let scale = std::cmp::min(
-        fb_config.width / (qr_with_quiet * 2),
-        available_height / qr_with_quiet
-    ).max(1);
-
-    let qr_pixel_size = qr_size * scale;
-    let quiet_zone_pixels = quiet_zone * scale;
-    let total_size = qr_pixel_size + (quiet_zone_pixels * 2);
-    let x_offset = (fb_config.width - total_size) / 2 + quiet_zone_pixels;
-    let y_offset = 80 + quiet_zone_pixels;
-
     // Create buffer and render display
-    let mut buffer = vec![0u8; fb_config.stride * fb_config.height * fb_config.bytes_per_pixel];
-    render_display(&mut buffer, &fb_config, &code, qr_size, qr_pixel_size, x_offset, y_offset, &state);
+    let mut buffer = vec![0u8; fb_config.stride * fb_config.height];
+    let font = Font::load(&config.fonts)?;
+    render_display(&mut buffer, &fb_config, &code, &state, config, &font);
 
     // Convert buffer to RGB for image crate
     let mut img: RgbImage = ImageBuffer::new(fb_config.width as u32, fb_config.height as u32);
     for y in 0..fb_config.height {
         for x in 0..fb_config.width {
-            let offset = (y * fb_config.stride + x) * fb_config.bytes_per_pixel;
-            let r = buffer[offset + fb_config.red_offset];
-            let g = buffer[offset + fb_config.green_offset];
-            let b = buffer[offset + fb_config.blue_offset];
+            let Color { r, g, b } = fb_config.format.read(&buffer[fb_config.offset(x, y)..]);
             img.put_pixel(x as u32, y as u32, Rgb([r, g, b]));
         }
     }
@@ -841,141 +1274,387 @@ fn render_to_image(output_path: &str) -> io::Result<()> {
     // Save the image
     img.save(output_path).map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
 
-    println!("Image saved to: {}", output_path);
+    println!("Image saved to: {}", output_path.display());
     Ok(())
 }
 
-fn draw_text(buffer: &mut [u8], fb_config: &FramebufferConfig, text: &str, x: usize, y: usize) {
-    draw_colored_text(buffer, fb_config, text, x, y, Color { r: 255, g: 255, b: 255 });
+fn fill_rect(buffer: &mut [u8], fb_config: &FramebufferConfig, rect: &layout::Rect, color: Color) {
+    for y in rect.y..rect.bottom() {
+        for x in rect.x..rect.right() {
+            fb_config.put_pixel(buffer, x, y, color);
+        }
+    }
 }
 
-fn draw_separator_line(buffer: &mut [u8], fb_config: &FramebufferConfig, x: usize, y: usize, length: usize) {
-    let color = Color { r: 100, g: 100, b: 100 }; // Gray color
-    let line_thickness = 2;
+/// Draw the segments of `row` on the character grid
+///
+/// All characters of a grapheme cluster are drawn at the same pen position,
+/// combining marks in the font are positioned relative to it. Bold text is
+/// drawn a second time slightly to the right.
+fn draw_row(
+    buffer: &mut [u8],
+    fb_config: &FramebufferConfig,
+    font: &Font,
+    layout: &layout::Layout,
+    row: &layout::TextRow,
+    screen: Color,
+) {
+    let mut cells = Vec::new();
+    let mut x = row.x;
+    for segment in &row.segments {
+        for (cluster, width) in text::clusters(&segment.text) {
+            let cell = layout::Rect { x, y: row.y, width: width * layout.char_width, height: layout.line_height };
+            cells.push((segment, cluster, cell));
+            x = cell.right();
+        }
+    }
 
-    for thickness in 0..line_thickness {
-        for i in 0..length {
-            let px = x + i;
-            let py = y + thickness;
+    // All backgrounds first, glyphs may reach into the neighbouring cells
+    for (segment, _, cell) in &cells {
+        if let (_, Some(background)) = segment.colors(screen) {
+            fill_rect(buffer, fb_config, cell, background);
+        }
+    }
 
-            if px < fb_config.width && py < fb_config.height {
-                let offset = (py * fb_config.stride + px) * fb_config.bytes_per_pixel;
-                buffer[offset + fb_config.red_offset] = color.r;
-                buffer[offset + fb_config.green_offset] = color.g;
-                buffer[offset + fb_config.blue_offset] = color.b;
-                if fb_config.bytes_per_pixel > 3 {
-                    buffer[offset + 3] = 0xFF;
-                }
+    let size = layout.text_size as usize;
+    let baseline = row.y + font.ascent(layout.text_size);
+    let stroke = (size / 16).max(1);
+    for (segment, cluster, cell) in &cells {
+        let (color, _) = segment.colors(screen);
+        for glyph in cluster.chars().filter_map(|ch| font.glyph(ch, layout.text_size)) {
+            draw_glyph(buffer, fb_config, &glyph, (cell.x, baseline), color);
+            if segment.bold {
+                draw_glyph(buffer, fb_config, &glyph, (cell.x + stroke, baseline), color);
             }
         }
+        if segment.underline {
+            let underline = layout::Rect { x: cell.x, y: baseline + stroke, width: cell.width, height: stroke };
+            fill_rect(buffer, fb_config, &underline, color);
+        }
     }
 }
 
-fn draw_colored_text(buffer: &mut [u8], fb_config: &FramebufferConfig, text: &str, x: usize, y: usize, color: Color) {
-    let scale = 2; // Make text 2x larger for better readability
-
-    for (char_idx, ch) in text.chars().enumerate() {
-        if let Some(glyph) = BASIC_FONTS.get(ch) {
-            let char_x = x + char_idx * 8 * scale;
-
-            // Draw each pixel of the character
-            for (row_idx, row) in glyph.iter().enumerate() {
-                for col in 0..8 {
-                    if row & (1 << col) != 0 {
-                        // Draw scaled pixel
-                        for dy in 0..scale {
-                            for dx in 0..scale {
-                                let px = char_x + col * scale + dx;
-                                let py = y + row_idx * scale + dy;
-
-                                if px < fb_config.width && py < fb_config.height {
-                                    let offset = (py * fb_config.stride + px) * fb_config.bytes_per_pixel;
-                                    buffer[offset + fb_config.red_offset] = color.r;
-                                    buffer[offset + fb_config.green_offset] = color.g;
-                                    buffer[offset + fb_config.blue_offset] = color.b;
-                                    if fb_config.bytes_per_pixel > 3 {
-                                        buffer[offset + 3] = 0xFF; // Alpha
-                                    }
-                                }
-                            }
-                        }
-                    }
-                }
+/// Blend `glyph` with its pen at `(x, baseline)`
+fn draw_glyph(buffer: &mut [u8], fb_config: &FramebufferConfig, glyph: &font::Glyph, pen: (usize, usize), color: Color) {
+    let left = pen.0 as i64 + glyph.left as i64;
+    let top = pen.1 as i64 + glyph.top as i64;
+    for (glyph_y, line) in glyph.coverage.chunks_exact(glyph.width).enumerate() {
+        for (glyph_x, &coverage) in line.iter().enumerate() {
+            let (x, y) = (left + glyph_x as i64, top + glyph_y as i64);
+            if x >= 0 && y >= 0 {
+                fb_config.blend_pixel(buffer, x as usize, y as usize, color, coverage);
             }
         }
     }
 }
 
-fn draw_logo(buffer: &mut [u8], fb_config: &FramebufferConfig, x: usize, y: usize) {
-    // Draw white background rectangle with some padding
-    let padding = 10;
-    for bg_y in 0..(LOGO_HEIGHT + 2 * padding) {
-        for bg_x in 0..(LOGO_WIDTH + 2 * padding) {
-            let px = x.saturating_sub(padding) + bg_x;
-            let py = y.saturating_sub(padding) + bg_y;
+/// Blend `logo`, scaled to `rect`, onto what is already drawn there
+fn draw_logo(buffer: &mut [u8], fb_config: &FramebufferConfig, logo: &Logo, rect: &layout::Rect) {
+    let bitmap = logo.scaled(rect.width, rect.height);
+    for (i, pixel) in bitmap.rgba.chunks_exact(4).enumerate() {
+        let color = Color { r: pixel[0], g: pixel[1], b: pixel[2] };
+        let (x, y) = (rect.x + i % bitmap.width, rect.y + i / bitmap.width);
+        fb_config.blend_pixel(buffer, x, y, color, pixel[3]);
+    }
+}
 
-            if px < fb_config.width && py < fb_config.height {
-                let offset = (py * fb_config.stride + px) * fb_config.bytes_per_pixel;
-                buffer[offset + fb_config.red_offset] = 0xFF;
-                buffer[offset + fb_config.green_offset] = 0xFF;
-                buffer[offset + fb_config.blue_offset] = 0xFF;
-                if fb_config.bytes_per_pixel > 3 {
-                    buffer[offset + 3] = 0xFF;
-                }
-            }
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::netlink::{Address, Scope};
+
+    fn bitfield(offset: u32, length: u32) -> FbBitfield {
+        FbBitfield { offset, length, msb_right: 0 }
+    }
+
+    /// `(offset, length)` of red, green, blue and transp
+    fn vinfo(bits_per_pixel: u32, [r, g, b, a]: [(u32, u32); 4]) -> FbVarScreeninfo {
+        FbVarScreeninfo {
+            bits_per_pixel,
+            red: bitfield(r.0, r.1),
+            green: bitfield(g.0, g.1),
+            blue: bitfield(b.0, b.1),
+            transp: bitfield(a.0, a.1),
+            ..Default::default()
         }
     }
 
-    // Draw logo on top of white background
-    for logo_y in 0..LOGO_HEIGHT {
-        for logo_x in 0..LOGO_WIDTH {
-            let logo_offset = (logo_y * LOGO_WIDTH + logo_x) * 4; // RGBA format
-            let r = LOGO_DATA[logo_offset];
-            let g = LOGO_DATA[logo_offset + 1];
-            let b = LOGO_DATA[logo_offset + 2];
-            let a = LOGO_DATA[logo_offset + 3];
-
-            // Only draw if not fully transparent
-            if a > 0 {
-                let px = x + logo_x;
-                let py = y + logo_y;
-
-                if px < fb_config.width && py < fb_config.height {
-                    let offset = (py * fb_config.stride + px) * fb_config.bytes_per_pixel;
-                    buffer[offset + fb_config.red_offset] = r;
-                    buffer[offset + fb_config.green_offset] = g;
-                    buffer[offset + fb_config.blue_offset] = b;
-                    if fb_config.bytes_per_pixel > 3 {
-                        buffer[offset + 3] = a; // Alpha
-                    }
-                }
-            }
+    /// Every layout `format_name` detects, with the pixel value (as a native
+    /// endian integer) that colour #123456 packs to
+    fn layouts() -> Vec<(&'static str, FbVarScreeninfo, u32)> {
+        vec![
+            ("RGB", vinfo(24, [(0, 8), (8, 8), (16, 8), (0, 0)]), 0x56_34_12),
+            ("RGB", vinfo(32, [(0, 8), (8, 8), (16, 8), (0, 0)]), 0x00_56_34_12),
+            ("BGR", vinfo(24, [(16, 8), (8, 8), (0, 8), (0, 0)]), 0x12_34_56),
+            ("BGR", vinfo(32, [(16, 8), (8, 8), (0, 8), (0, 0)]), 0x00_12_34_56),
+            ("BGRA", vinfo(32, [(16, 8), (8, 8), (0, 8), (24, 8)]), 0xFF_12_34_56),
+            ("RGBA", vinfo(32, [(0, 8), (8, 8), (16, 8), (24, 8)]), 0xFF_56_34_12),
+            ("ABGR", vinfo(32, [(24, 8), (16, 8), (8, 8), (0, 8)]), 0x12_34_56_FF),
+            ("ARGB", vinfo(32, [(8, 8), (16, 8), (24, 8), (0, 8)]), 0x56_34_12_FF),
+            ("RGB565", vinfo(16, [(11, 5), (5, 6), (0, 5), (0, 0)]), 0x11_AA),
+            ("BGR565", vinfo(16, [(0, 5), (5, 6), (11, 5), (0, 0)]), 0x51_A2),
+            ("RGB555", vinfo(16, [(10, 5), (5, 5), (0, 5), (0, 0)]), 0x08_CA),
+            // Same memory layout, some drivers count only the used bits
+            ("RGB555", vinfo(15, [(10, 5), (5, 5), (0, 5), (0, 0)]), 0x08_CA),
+        ]
+    }
+
+    pub(crate) fn test_state() -> DisplayState {
+        let mut state = DisplayState {
+            root_password: "correct-horse-battery".to_string(),
+            onion_hostname: "abcdefghijklmnop.onion".to_string(),
+            login_json: String::new(),
+            interfaces: vec![Interface {
+                index: 2,
+                name: "eth0".to_string(),
+                operstate: OperState::Up,
+                mac: vec![0x52, 0x54, 0x00, 0x12, 0x34, 0x56],
+                flags: 0,
+                addrs: vec![
+                    Address {
+                        family: Family::Inet,
+                        addr: "192.0.2.2".parse().unwrap(),
+                        prefix_len: 24,
+                        scope: Scope::Universe,
+                        flags: 0,
+                    },
+                    Address {
+                        family: Family::Inet6,
+                        addr: "2001:db8::2".parse().unwrap(),
+                        prefix_len: 64,
+                        scope: Scope::Universe,
+                        flags: 0,
+                    },
+                ],
+            }],
+            hostname: "nixos".to_string(),
+            host_keys: vec![HostKey {
+                algorithm: "ssh-ed25519".to_string(),
+                public_key: "ssh-ed25519 AAAA".to_string(),
+                fingerprint: "SHA256:abc".to_string(),
+            }],
+            ssh_port: 22,
+        };
+        state.login_json = login::generate_login_json(&state);
+        state
+    }
+
+    /// Render the test scene into an 800x600 buffer with padded lines, the
+    /// padding isn't a whole number of 24 bit pixels
+    fn render(format: PixelFormat) -> (FramebufferConfig, Vec<u8>) {
+        render_rotated(format, Rotation::Normal)
+    }
+
+    /// The 800x600 test scene on a framebuffer turned by `rotation`
+    fn render_rotated(format: PixelFormat, rotation: Rotation) -> (FramebufferConfig, Vec<u8>) {
+        let (width, height) = rotation.screen_size(800, 600);
+        let fb_config = FramebufferConfig {
+            width,
+            height,
+            stride: width * format.bytes_per_pixel + 100,
+            dpi: None,
+            format,
+            rotation,
+        };
+        let state = test_state();
+        let code = QrCode::new(&state.login_json).unwrap();
+        let mut buffer = vec![0u8; fb_config.stride * fb_config.height];
+        render_display(&mut buffer, &fb_config, &code, &state, &Config::default(), &Font::embedded());
+        (fb_config, buffer)
+    }
+
+    /// The raw pixel value at `(x, y)` as a native endian integer
+    fn raw_pixel(fb_config: &FramebufferConfig, buffer: &[u8], x: usize, y: usize) -> u32 {
+        let len = fb_config.format.bytes_per_pixel;
+        let offset = fb_config.offset(x, y);
+        let mut bytes = [0u8; 4];
+        if cfg!(target_endian = "little") {
+            bytes[..len].copy_from_slice(&buffer[offset..offset + len]);
+        } else {
+            bytes[4 - len..].copy_from_slice(&buffer[offset..offset + len]);
         }
+        u32::from_ne_bytes(bytes)
     }
-}
 
-fn draw_colored_line(buffer: &mut [u8], fb_config: &FramebufferConfig, text: &str, x: usize, y: usize) -> usize {
-    let segments = parse_colored_text(text);
-    let scale = 2;
-    let char_width = 8 * scale;
-    let line_height = 20;
-    let max_width = fb_config.width - 100;
-    let mut current_x = x;
-    let mut lines_used = 0;
+    #[test]
+    fn detects_every_layout() {
+        for (name, vinfo, _) in layouts() {
+            assert_eq!(vinfo.format_name(), name, "{}bpp", vinfo.bits_per_pixel);
+        }
+        let unknown = vinfo(32, [(0, 8), (16, 8), (8, 8), (0, 0)]);
+        assert_eq!(unknown.format_name(), "Custom/Unknown");
+    }
 
-    for segment in segments {
-        let segment_width = segment.text.len() * char_width;
+    #[test]
+    fn visible_window_follows_pan_and_line_length() {
+        // 1366x768 at 24 bits with lines padded to 4 bytes, panned to the
+        // second of two screens
+        let vinfo = FbVarScreeninfo {
+            xres: 1366,
+            yres: 768,
+            xres_virtual: 1366,
+            yres_virtual: 1536,
+            yoffset: 768,
+            ..vinfo(24, [(16, 8), (8, 8), (0, 8), (0, 0)])
+        };
+        let finfo = FbFixScreeninfo { line_length: 4100, smem_len: 4100 * 1536, ..Default::default() };
+        let window = visible_window(&vinfo, &finfo).unwrap();
+        assert_eq!(window, VisibleWindow { stride: 4100, origin: 4100 * 768, memory: 4100 * 1536 });
+
+        // Drivers that don't fill in the fixed info
+        let window = visible_window(&vinfo, &FbFixScreeninfo::default()).unwrap();
+        assert_eq!(window, VisibleWindow { stride: 4098, origin: 4098 * 768, memory: 4098 * 1536 });
+
+        let short = FbFixScreeninfo { smem_len: 4100 * 768, ..finfo };
+        assert!(visible_window(&vinfo, &short).unwrap_err().contains("smem_len"));
+        let narrow = FbFixScreeninfo { line_length: 4000, ..finfo };
+        assert!(visible_window(&vinfo, &narrow).unwrap_err().contains("line_length"));
+    }
 
-        // Check if we need to wrap
-        if current_x + segment_width > x + max_width && current_x > x {
-            // Move to next line
-            lines_used += 1;
-            current_x = x + 20; // Indent wrapped lines
+    #[test]
+    fn mode_changes_are_noticed() {
+        let rgb565 = || FbVarScreeninfo { xres: 1024, yres: 768, ..vinfo(16, [(11, 5), (5, 6), (0, 5), (0, 0)]) };
+        let fixed = || FbFixScreeninfo { line_length: 2048, smem_len: 2048 * 768, ..Default::default() };
+        let mode = FbMode::new(&rgb565(), &fixed());
+
+        // Panning doesn't need the device opened again
+        assert_eq!(FbMode::new(&FbVarScreeninfo { yoffset: 10, ..rgb565() }, &fixed()), mode);
+
+        let changed = [
+            (FbVarScreeninfo { xres: 800, ..rgb565() }, fixed()),
+            (FbVarScreeninfo { yres: 600, ..rgb565() }, fixed()),
+            (FbVarScreeninfo { rotate: 1, ..rgb565() }, fixed()),
+            (rgb565(), FbFixScreeninfo { line_length: 2560, ..fixed() }),
+            (rgb565(), FbFixScreeninfo { smem_len: 2048 * 1536, ..fixed() }),
+            // RGB555, at the same depth
+            (FbVarScreeninfo { xres: 1024, yres: 768, ..vinfo(16, [(10, 5), (5, 5), (0, 5), (0, 0)]) }, fixed()),
+            (FbVarScreeninfo { xres: 1024, yres: 768, ..vinfo(32, [(16, 8), (8, 8), (0, 8), (0, 0)]) }, fixed()),
+        ];
+        for (vinfo, finfo) in changed {
+            let changed = FbMode::new(&vinfo, &finfo);
+            assert_ne!(changed, mode, "{:?}", changed);
         }
+    }
 
-        draw_colored_text(buffer, fb_config, &segment.text, current_x, y + (lines_used * line_height), segment.color);
-        current_x += segment_width;
+    #[test]
+    fn put_pixel_in_every_layout() {
+        for (name, vinfo, expected) in layouts() {
+            let fb_config = FramebufferConfig {
+                width: 2,
+                height: 2,
+                stride: 3 * vinfo.pixel_format().bytes_per_pixel,
+                dpi: None,
+                format: vinfo.pixel_format(),
+                rotation: Rotation::Normal,
+            };
+            let mut buffer = vec![0u8; fb_config.stride * 2];
+            fb_config.put_pixel(&mut buffer, 1, 1, Color { r: 0x12, g: 0x34, b: 0x56 });
+            assert_eq!(raw_pixel(&fb_config, &buffer, 1, 1), expected, "{}", name);
+
+            // Nothing else is touched, including outside the visible area
+            fb_config.put_pixel(&mut buffer, 2, 0, Color { r: 0xFF, g: 0xFF, b: 0xFF });
+            fb_config.put_pixel(&mut buffer, 0, 2, Color { r: 0xFF, g: 0xFF, b: 0xFF });
+            for (x, y) in [(0, 0), (1, 0), (2, 0), (0, 1), (2, 1)] {
+                assert_eq!(raw_pixel(&fb_config, &buffer, x, y), 0, "{} at ({}, {})", name, x, y);
+            }
+        }
+    }
+
+    #[test]
+    fn background_is_opaque() {
+        for (name, vinfo, _) in layouts() {
+            let (fb_config, buffer) = render(vinfo.pixel_format());
+            let alpha = fb_config.format.alpha;
+            let expected = if alpha.length == 0 { 0 } else { 0xFF << alpha.offset };
+            assert_eq!(raw_pixel(&fb_config, &buffer, 0, 0), expected, "{}", name);
+        }
+    }
+
+    #[test]
+    fn same_scene_in_every_layout() {
+        let (reference_config, reference) = render(layouts()[4].1.pixel_format());
+        let mut formats: Vec<(&str, PixelFormat)> = layouts()
+            .into_iter()
+            .map(|(name, vinfo, _)| (name, vinfo.pixel_format()))
+            .collect();
+        formats.push(("RGB565 msb_right", PixelFormat {
+            bytes_per_pixel: 2,
+            red: Channel { offset: 11, length: 5, msb_right: true },
+            green: Channel { offset: 5, length: 6, msb_right: true },
+            blue: Channel { offset: 0, length: 5, msb_right: true },
+            alpha: Channel::new(0, 0),
+        }));
+
+        for (name, format) in formats {
+            let (fb_config, buffer) = render(format);
+            for y in 0..fb_config.height {
+                for x in 0..fb_config.width {
+                    let expected = format.quantize(reference_config.format.read(&reference[reference_config.offset(x, y)..]));
+                    let actual = format.read(&buffer[fb_config.offset(x, y)..]);
+                    assert_eq!(actual, expected, "{} differs at ({}, {})", name, x, y);
+                }
+            }
+        }
     }
 
-    lines_used + 1 // Return number of lines used
+    #[test]
+    fn rotated_scene_is_the_same_picture() {
+        let format = layouts()[4].1.pixel_format();
+        let (reference_config, reference) = render(format);
+        for rotation in [Rotation::Clockwise, Rotation::UpsideDown, Rotation::CounterClockwise] {
+            let (fb_config, buffer) = render_rotated(format, rotation);
+            for y in 0..600 {
+                for x in 0..800 {
+                    let (fb_x, fb_y) = rotation.to_framebuffer(x, y, fb_config.width, fb_config.height);
+                    let expected = format.read(&reference[reference_config.offset(x, y)..]);
+                    let actual = format.read(&buffer[fb_config.offset(fb_x, fb_y)..]);
+                    assert_eq!(actual, expected, "{:?} differs at ({}, {})", rotation, x, y);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn text_backgrounds_and_underline() {
+        let format = layouts()[4].1.pixel_format();
+        let fb_config = FramebufferConfig {
+            width: 640,
+            height: 480,
+            stride: 640 * 4,
+            dpi: None,
+            format,
+            rotation: Rotation::Normal,
+        };
+        let font = Font::embedded();
+        let sections = [layout::Section {
+            title: "Title".to_string(),
+            lines: vec!["\x1b[41m  \x1b[0m \x1b[4;32mu\x1b[7mi".to_string()],
+        }];
+        let theme = Theme { logo: None, ..Theme::default() };
+        let content = layout::Content {
+            sections: &sections,
+            qr_modules: 21,
+            font: &font,
+            font_size: None,
+            theme: &theme,
+        };
+        let layout = layout::compute(640, 480, None, layout::Mode::Below, &content);
+        let row = &layout.rows[1];
+        let mut buffer = vec![0u8; 640 * 480 * 4];
+        draw_row(&mut buffer, &fb_config, &font, &layout, row, theme.background);
+
+        let pixel = |x: usize, y: usize| format.read(&buffer[fb_config.offset(x, y)..]);
+        let cell = |n: usize| row.x + n * layout.char_width;
+        let bottom = row.y + layout.line_height - 1;
+        // Backgrounds fill whole cells, so neighbours touch
+        assert_eq!(pixel(cell(0), row.y), ansi::PALETTE[1]);
+        assert_eq!(pixel(cell(2) - 1, bottom), ansi::PALETTE[1]);
+        assert_eq!(pixel(cell(2), row.y), theme.background);
+        // The underline runs from edge to edge of the cell, below the baseline
+        let baseline = row.y + font.ascent(layout.text_size);
+        assert!((baseline..bottom).any(|y| pixel(cell(3), y) == ansi::PALETTE[2]));
+        assert_eq!(pixel(cell(3), row.y), theme.background);
+        // Inverse video: the text colour becomes the background
+        assert_eq!(pixel(cell(4), row.y), ansi::PALETTE[2]);
+    }
 }
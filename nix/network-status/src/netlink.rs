@@ -0,0 +1,642 @@
+//! Minimal rtnetlink client for enumerating interfaces and their addresses
+//! and for getting notified when they change
+//!
+//! Talks to the kernel directly over a NETLINK_ROUTE socket, so we neither
+//! depend on iproute2 being installed nor on the format of its output.
+
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::os::unix::io::{AsRawFd, FromRawFd, OwnedFd};
+
+// Constants from <linux/netlink.h> and <linux/rtnetlink.h>
+const NLMSG_ERROR: u16 = 0x2;
+const NLMSG_DONE: u16 = 0x3;
+const NLM_F_REQUEST: u16 = 0x01;
+const NLM_F_DUMP: u16 = 0x300;
+
+const RTM_NEWLINK: u16 = 16;
+const RTM_GETLINK: u16 = 18;
+const RTM_NEWADDR: u16 = 20;
+const RTM_GETADDR: u16 = 22;
+
+// Attributes from <linux/if_link.h> and <linux/if_addr.h>
+const IFLA_ADDRESS: u16 = 1;
+const IFLA_IFNAME: u16 = 3;
+const IFLA_OPERSTATE: u16 = 16;
+const IFA_ADDRESS: u16 = 1;
+const IFA_LOCAL: u16 = 2;
+const IFA_FLAGS: u16 = 8;
+
+// Multicast groups from <linux/rtnetlink.h>
+const RTNLGRP_LINK: u32 = 1;
+const RTNLGRP_IPV4_IFADDR: u32 = 5;
+const RTNLGRP_IPV6_IFADDR: u32 = 9;
+const SOL_NETLINK: libc::c_int = 270;
+const NETLINK_ADD_MEMBERSHIP: libc::c_int = 1;
+
+// Mask off NLA_F_NESTED and NLA_F_NET_BYTEORDER
+const NLA_TYPE_MASK: u16 = 0x3fff;
+
+const IFF_LOOPBACK: u32 = 0x8;
+const IFA_F_DADFAILED: u32 = 0x08;
+const IFA_F_TENTATIVE: u32 = 0x40;
+
+const NLMSG_HDRLEN: usize = 16;
+const IFINFOMSG_LEN: usize = 16;
+const IFADDRMSG_LEN: usize = 8;
+
+#[repr(C)]
+struct NlMsgHdr {
+    nlmsg_len: u32,
+    nlmsg_type: u16,
+    nlmsg_flags: u16,
+    nlmsg_seq: u32,
+    nlmsg_pid: u32,
+}
+
+/// Dump request: header followed by a zeroed ifinfomsg/ifaddrmsg (AF_UNSPEC)
+#[repr(C)]
+struct DumpRequest {
+    header: NlMsgHdr,
+    body: [u8; IFINFOMSG_LEN],
+}
+
+/// RFC 2863 operational state as reported in IFLA_OPERSTATE
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OperState {
+    Unknown,
+    NotPresent,
+    Down,
+    LowerLayerDown,
+    Testing,
+    Dormant,
+    Up,
+}
+
+impl OperState {
+    fn from_raw(value: u8) -> Self {
+        match value {
+            1 => OperState::NotPresent,
+            2 => OperState::Down,
+            3 => OperState::LowerLayerDown,
+            4 => OperState::Testing,
+            5 => OperState::Dormant,
+            6 => OperState::Up,
+            _ => OperState::Unknown,
+        }
+    }
+
+    /// Name as printed by `ip link`
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            OperState::Unknown => "UNKNOWN",
+            OperState::NotPresent => "NOTPRESENT",
+            OperState::Down => "DOWN",
+            OperState::LowerLayerDown => "LOWERLAYERDOWN",
+            OperState::Testing => "TESTING",
+            OperState::Dormant => "DORMANT",
+            OperState::Up => "UP",
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Family {
+    Inet,
+    Inet6,
+}
+
+impl Family {
+    /// Name as printed by `ip`
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Family::Inet => "inet",
+            Family::Inet6 => "inet6",
+        }
+    }
+}
+
+/// Address scope (RT_SCOPE_*)
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Scope {
+    Universe,
+    Site,
+    Link,
+    Host,
+    Nowhere,
+    Other(u8),
+}
+
+impl Scope {
+    fn from_raw(value: u8) -> Self {
+        match value {
+            0 => Scope::Universe,
+            200 => Scope::Site,
+            253 => Scope::Link,
+            254 => Scope::Host,
+            255 => Scope::Nowhere,
+            other => Scope::Other(other),
+        }
+    }
+
+    /// Name as printed by `ip addr`, or the raw number for unnamed scopes
+    pub fn name(&self) -> String {
+        match self {
+            Scope::Universe => "global".to_string(),
+            Scope::Site => "site".to_string(),
+            Scope::Link => "link".to_string(),
+            Scope::Host => "host".to_string(),
+            Scope::Nowhere => "nowhere".to_string(),
+            Scope::Other(value) => value.to_string(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Address {
+    pub family: Family,
+    pub addr: IpAddr,
+    pub prefix_len: u8,
+    pub scope: Scope,
+    pub flags: u32,
+}
+
+impl Address {
+    /// Whether a remote peer can currently reach us on this address
+    /// (not loopback-scoped and not stuck in duplicate address detection)
+    pub fn is_usable(&self) -> bool {
+        self.scope != Scope::Host && self.flags & (IFA_F_TENTATIVE | IFA_F_DADFAILED) == 0
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Interface {
+    pub index: u32,
+    pub name: String,
+    pub operstate: OperState,
+    pub mac: Vec<u8>,
+    pub flags: u32,
+    pub addrs: Vec<Address>,
+}
+
+impl Interface {
+    pub fn is_loopback(&self) -> bool {
+        self.flags & IFF_LOOPBACK != 0
+    }
+
+    /// Hardware address formatted as colon separated hex, empty if there is none
+    pub fn mac_string(&self) -> String {
+        self.mac
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect::<Vec<_>>()
+            .join(":")
+    }
+}
+
+/// Enumerate all interfaces together with their addresses
+pub fn list_interfaces() -> io::Result<Vec<Interface>> {
+    let socket = open_socket(0)?;
+
+    let mut interfaces = Vec::new();
+    for payload in dump(&socket, RTM_GETLINK, RTM_NEWLINK, 1)? {
+        if let Some(interface) = parse_link(&payload) {
+            interfaces.push(interface);
+        }
+    }
+
+    for payload in dump(&socket, RTM_GETADDR, RTM_NEWADDR, 2)? {
+        if let Some((index, address)) = parse_addr(&payload) {
+            if let Some(interface) = interfaces.iter_mut().find(|i| i.index == index) {
+                interface.addrs.push(address);
+            }
+        }
+    }
+
+    interfaces.sort_by_key(|i| i.index);
+    Ok(interfaces)
+}
+
+/// Subscription to link and address change notifications
+///
+/// The socket only serves as a wakeup source: callers re-read the full state
+/// with `list_interfaces` after `drain` reports activity.
+pub struct Monitor {
+    socket: OwnedFd,
+}
+
+impl Monitor {
+    pub fn new() -> io::Result<Self> {
+        let socket = open_socket(libc::SOCK_NONBLOCK)?;
+
+        for group in [RTNLGRP_LINK, RTNLGRP_IPV4_IFADDR, RTNLGRP_IPV6_IFADDR] {
+            let ret = unsafe {
+                libc::setsockopt(
+                    socket.as_raw_fd(),
+                    SOL_NETLINK,
+                    NETLINK_ADD_MEMBERSHIP,
+                    &group as *const u32 as *const libc::c_void,
+                    std::mem::size_of::<u32>() as libc::socklen_t,
+                )
+            };
+            if ret < 0 {
+                return Err(io::Error::last_os_error());
+            }
+        }
+
+        Ok(Monitor { socket })
+    }
+
+    /// Discard all pending notifications
+    pub fn drain(&self) {
+        let mut buf = [0u8; 8192];
+        loop {
+            let len = unsafe {
+                libc::recv(self.socket.as_raw_fd(), buf.as_mut_ptr() as *mut libc::c_void, buf.len(), 0)
+            };
+            if len > 0 {
+                continue;
+            }
+            match io::Error::last_os_error().raw_os_error() {
+                // ENOBUFS means the queue overflowed, which is fine since the
+                // caller re-reads everything anyway
+                Some(libc::EINTR) | Some(libc::ENOBUFS) if len < 0 => continue,
+                _ => return,
+            }
+        }
+    }
+}
+
+impl AsRawFd for Monitor {
+    fn as_raw_fd(&self) -> std::os::unix::io::RawFd {
+        self.socket.as_raw_fd()
+    }
+}
+
+/// Open and bind a NETLINK_ROUTE socket
+fn open_socket(flags: libc::c_int) -> io::Result<OwnedFd> {
+    let fd = unsafe {
+        libc::socket(
+            libc::AF_NETLINK,
+            libc::SOCK_RAW | libc::SOCK_CLOEXEC | flags,
+            libc::NETLINK_ROUTE,
+        )
+    };
+    if fd < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    let socket = unsafe { OwnedFd::from_raw_fd(fd) };
+
+    let mut addr: libc::sockaddr_nl = unsafe { std::mem::zeroed() };
+    addr.nl_family = libc::AF_NETLINK as libc::sa_family_t;
+    let ret = unsafe {
+        libc::bind(
+            socket.as_raw_fd(),
+            &addr as *const libc::sockaddr_nl as *const libc::sockaddr,
+            std::mem::size_of::<libc::sockaddr_nl>() as libc::socklen_t,
+        )
+    };
+    if ret < 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    Ok(socket)
+}
+
+/// Send a dump request and collect the payloads of all `reply_type` messages
+fn dump(socket: &OwnedFd, request_type: u16, reply_type: u16, seq: u32) -> io::Result<Vec<Vec<u8>>> {
+    let body_len = if request_type == RTM_GETADDR { IFADDRMSG_LEN } else { IFINFOMSG_LEN };
+    let request = DumpRequest {
+        header: NlMsgHdr {
+            nlmsg_len: (NLMSG_HDRLEN + body_len) as u32,
+            nlmsg_type: request_type,
+            nlmsg_flags: NLM_F_REQUEST | NLM_F_DUMP,
+            nlmsg_seq: seq,
+            nlmsg_pid: 0,
+        },
+        body: [0; IFINFOMSG_LEN],
+    };
+
+    let ret = unsafe {
+        libc::send(
+            socket.as_raw_fd(),
+            &request as *const DumpRequest as *const libc::c_void,
+            request.header.nlmsg_len as usize,
+            0,
+        )
+    };
+    if ret < 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    let mut payloads = Vec::new();
+    let mut buf = vec![0u8; 32 * 1024];
+    loop {
+        let len = unsafe {
+            libc::recv(socket.as_raw_fd(), buf.as_mut_ptr() as *mut libc::c_void, buf.len(), 0)
+        };
+        if len < 0 {
+            let err = io::Error::last_os_error();
+            if err.kind() == io::ErrorKind::Interrupted {
+                continue;
+            }
+            return Err(err);
+        }
+
+        if collect_replies(&buf[..len as usize], reply_type, &mut payloads)? {
+            return Ok(payloads);
+        }
+    }
+}
+
+/// Add the payloads of the `reply_type` messages in a datagram to
+/// `payloads`, returns true once the dump is done
+fn collect_replies(buf: &[u8], reply_type: u16, payloads: &mut Vec<Vec<u8>>) -> io::Result<bool> {
+    for (msg_type, payload) in messages(buf) {
+        match msg_type {
+            NLMSG_DONE => return Ok(true),
+            NLMSG_ERROR => {
+                let errno = payload
+                    .get(0..4)
+                    .map(|b| i32::from_ne_bytes([b[0], b[1], b[2], b[3]]))
+                    .unwrap_or(0);
+                if errno != 0 {
+                    return Err(io::Error::from_raw_os_error(-errno));
+                }
+            }
+            t if t == reply_type => payloads.push(payload.to_vec()),
+            _ => {}
+        }
+    }
+    Ok(false)
+}
+
+/// Split a datagram into (type, payload) pairs
+fn messages(mut buf: &[u8]) -> Vec<(u16, &[u8])> {
+    let mut result = Vec::new();
+    while buf.len() >= NLMSG_HDRLEN {
+        let len = u32::from_ne_bytes([buf[0], buf[1], buf[2], buf[3]]) as usize;
+        let msg_type = u16::from_ne_bytes([buf[4], buf[5]]);
+        if len < NLMSG_HDRLEN || len > buf.len() {
+            break;
+        }
+        result.push((msg_type, &buf[NLMSG_HDRLEN..len]));
+        buf = &buf[align(len).min(buf.len())..];
+    }
+    result
+}
+
+/// Iterate over the route attributes in `buf` as (type, value) pairs
+fn attributes(mut buf: &[u8]) -> Vec<(u16, &[u8])> {
+    let mut result = Vec::new();
+    while buf.len() >= 4 {
+        let len = u16::from_ne_bytes([buf[0], buf[1]]) as usize;
+        let attr_type = u16::from_ne_bytes([buf[2], buf[3]]) & NLA_TYPE_MASK;
+        if len < 4 || len > buf.len() {
+            break;
+        }
+        result.push((attr_type, &buf[4..len]));
+        buf = &buf[align(len).min(buf.len())..];
+    }
+    result
+}
+
+fn align(len: usize) -> usize {
+    (len + 3) & !3
+}
+
+/// Parse the payload of an RTM_NEWLINK message
+fn parse_link(payload: &[u8]) -> Option<Interface> {
+    if payload.len() < IFINFOMSG_LEN {
+        return None;
+    }
+    // struct ifinfomsg { u8 family; u8 pad; u16 type; i32 index; u32 flags; u32 change; }
+    let index = i32::from_ne_bytes(payload[4..8].try_into().ok()?) as u32;
+    let flags = u32::from_ne_bytes(payload[8..12].try_into().ok()?);
+
+    let mut interface = Interface {
+        index,
+        name: String::new(),
+        operstate: OperState::Unknown,
+        mac: Vec::new(),
+        flags,
+        addrs: Vec::new(),
+    };
+
+    for (attr_type, value) in attributes(&payload[IFINFOMSG_LEN..]) {
+        match attr_type {
+            IFLA_IFNAME => {
+                let name = value.split(|&b| b == 0).next().unwrap_or(&[]);
+                interface.name = String::from_utf8_lossy(name).into_owned();
+            }
+            IFLA_ADDRESS => interface.mac = value.to_vec(),
+            IFLA_OPERSTATE => {
+                if let Some(&state) = value.first() {
+                    interface.operstate = OperState::from_raw(state);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    Some(interface)
+}
+
+/// Parse the payload of an RTM_NEWADDR message into (interface index, address)
+fn parse_addr(payload: &[u8]) -> Option<(u32, Address)> {
+    if payload.len() < IFADDRMSG_LEN {
+        return None;
+    }
+    // struct ifaddrmsg { u8 family; u8 prefixlen; u8 flags; u8 scope; u32 index; }
+    let family = match payload[0] as i32 {
+        libc::AF_INET => Family::Inet,
+        libc::AF_INET6 => Family::Inet6,
+        _ => return None,
+    };
+    let prefix_len = payload[1];
+    let mut flags = payload[2] as u32;
+    let scope = Scope::from_raw(payload[3]);
+    let index = u32::from_ne_bytes(payload[4..8].try_into().ok()?);
+
+    let mut local = None;
+    let mut address = None;
+    for (attr_type, value) in attributes(&payload[IFADDRMSG_LEN..]) {
+        match attr_type {
+            IFA_LOCAL => local = parse_ip(family, value),
+            IFA_ADDRESS => address = parse_ip(family, value),
+            IFA_FLAGS => {
+                if let Ok(bytes) = value.try_into() {
+                    flags = u32::from_ne_bytes(bytes);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    // On point-to-point links IFA_ADDRESS is the peer, IFA_LOCAL is ours
+    let addr = local.or(address)?;
+    Some((index, Address { family, addr, prefix_len, scope, flags }))
+}
+
+fn parse_ip(family: Family, value: &[u8]) -> Option<IpAddr> {
+    match family {
+        Family::Inet => {
+            let octets: [u8; 4] = value.try_into().ok()?;
+            Some(IpAddr::V4(Ipv4Addr::from(octets)))
+        }
+        Family::Inet6 => {
+            let octets: [u8; 16] = value.try_into().ok()?;
+            Some(IpAddr::V6(Ipv6Addr::from(octets)))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// An attribute, padded to 4 bytes
+    fn attribute(attr_type: u16, value: &[u8]) -> Vec<u8> {
+        let mut buf = ((4 + value.len()) as u16).to_ne_bytes().to_vec();
+        buf.extend(attr_type.to_ne_bytes());
+        buf.extend(value);
+        buf.resize(align(buf.len()), 0);
+        buf
+    }
+
+    /// A netlink message, padded to 4 bytes
+    fn message(msg_type: u16, payload: &[u8]) -> Vec<u8> {
+        let mut buf = ((NLMSG_HDRLEN + payload.len()) as u32).to_ne_bytes().to_vec();
+        buf.extend(msg_type.to_ne_bytes());
+        buf.extend([0; 10]);
+        buf.extend(payload);
+        buf.resize(align(buf.len()), 0);
+        buf
+    }
+
+    /// An RTM_NEWADDR payload for interface 3 with `attributes`
+    fn addr_payload(family: i32, prefix_len: u8, flags: u8, attributes: &[Vec<u8>]) -> Vec<u8> {
+        let mut buf = vec![family as u8, prefix_len, flags, 0];
+        buf.extend(3u32.to_ne_bytes());
+        buf.extend(attributes.concat());
+        buf
+    }
+
+    #[test]
+    fn link() {
+        let mut payload = vec![0; IFINFOMSG_LEN];
+        payload[4..8].copy_from_slice(&2i32.to_ne_bytes());
+        payload[8..12].copy_from_slice(&IFF_LOOPBACK.to_ne_bytes());
+        payload.extend(attribute(IFLA_IFNAME, b"lo\0"));
+        payload.extend(attribute(IFLA_ADDRESS, &[0x52, 0x54, 0, 0x12, 0x34, 0x56]));
+        payload.extend(attribute(IFLA_OPERSTATE, &[6]));
+
+        let interface = parse_link(&payload).unwrap();
+        assert_eq!(interface.index, 2);
+        assert_eq!(interface.name, "lo");
+        assert_eq!(interface.mac_string(), "52:54:00:12:34:56");
+        assert_eq!(interface.operstate, OperState::Up);
+        assert!(interface.is_loopback());
+        assert_eq!(parse_link(&payload[..IFINFOMSG_LEN - 1]), None);
+    }
+
+    #[test]
+    fn local_address_wins_on_point_to_point_links() {
+        let payload = addr_payload(
+            libc::AF_INET,
+            32,
+            0,
+            &[attribute(IFA_ADDRESS, &[10, 0, 0, 2]), attribute(IFA_LOCAL, &[10, 0, 0, 1])],
+        );
+        let (index, address) = parse_addr(&payload).unwrap();
+        assert_eq!(index, 3);
+        assert_eq!(address.addr, IpAddr::from([10, 0, 0, 1]));
+        assert_eq!(address.prefix_len, 32);
+
+        // Without IFA_LOCAL, IFA_ADDRESS is our own address
+        let link_local: Ipv6Addr = "fe80::1".parse().unwrap();
+        let payload = addr_payload(libc::AF_INET6, 64, 0, &[attribute(IFA_ADDRESS, &link_local.octets())]);
+        let (_, address) = parse_addr(&payload).unwrap();
+        assert_eq!(address.family, Family::Inet6);
+        assert_eq!(address.addr, IpAddr::V6(link_local));
+
+        // A value of the wrong length for the family is no address
+        let payload = addr_payload(libc::AF_INET, 24, 0, &[attribute(IFA_ADDRESS, &[10, 0, 0])]);
+        assert_eq!(parse_addr(&payload), None);
+    }
+
+    #[test]
+    fn ifa_flags_override_the_header_flags() {
+        // IFA_F_TENTATIVE fits in the header, IFA_FLAGS has the full set
+        let payload = addr_payload(
+            libc::AF_INET6,
+            64,
+            IFA_F_TENTATIVE as u8,
+            &[attribute(IFA_ADDRESS, &[0; 16]), attribute(IFA_FLAGS, &0x100u32.to_ne_bytes())],
+        );
+        let (_, address) = parse_addr(&payload).unwrap();
+        assert_eq!(address.flags, 0x100);
+        assert!(address.is_usable());
+
+        // Without IFA_FLAGS the header's flags stay
+        let payload = addr_payload(libc::AF_INET6, 64, IFA_F_TENTATIVE as u8, &[attribute(IFA_ADDRESS, &[0; 16])]);
+        let (_, address) = parse_addr(&payload).unwrap();
+        assert_eq!(address.flags, IFA_F_TENTATIVE);
+        assert!(!address.is_usable());
+    }
+
+    #[test]
+    fn truncated_and_misaligned_input() {
+        let name = attribute(IFLA_IFNAME, b"eth0\0");
+        // Shorter than its length says
+        assert_eq!(attributes(&name[..name.len() - 4]), []);
+        // Less than a header, and a length below the header size
+        assert_eq!(attributes(&name[..3]), []);
+        assert_eq!(attributes(&[2, 0, 3, 0, 0, 0, 0, 0]), []);
+        // A last attribute without its padding
+        let mut buf = attribute(IFLA_OPERSTATE, &[6]);
+        buf.extend(&name[..9]);
+        assert_eq!(attributes(&buf), [(IFLA_OPERSTATE, &[6][..]), (IFLA_IFNAME, &b"eth0\0"[..])]);
+
+        let done = message(NLMSG_DONE, &[0; 4]);
+        assert_eq!(messages(&done[..NLMSG_HDRLEN - 1]), []);
+        assert_eq!(messages(&done[..done.len() - 1]), []);
+        let mut short = done.clone();
+        short[0..4].copy_from_slice(&4u32.to_ne_bytes());
+        assert_eq!(messages(&short), []);
+        // nlmsg_len not a multiple of 4, and nothing after it
+        let mut odd = message(RTM_NEWADDR, &[1]);
+        odd.truncate(NLMSG_HDRLEN + 1);
+        assert_eq!(messages(&odd), [(RTM_NEWADDR, &[1][..])]);
+
+        // Payloads too short for their fixed header
+        assert_eq!(parse_addr(&[libc::AF_INET as u8, 24, 0, 0]), None);
+        assert_eq!(parse_addr(&addr_payload(libc::AF_PACKET, 0, 0, &[])), None);
+    }
+
+    #[test]
+    fn dump_ends_with_done_or_error() {
+        let payload = addr_payload(libc::AF_INET, 24, 0, &[attribute(IFA_ADDRESS, &[192, 0, 2, 1])]);
+        let mut datagram = message(RTM_NEWADDR, &payload);
+        datagram.extend(message(RTM_NEWLINK, &[0; IFINFOMSG_LEN]));
+
+        let mut payloads = Vec::new();
+        assert!(!collect_replies(&datagram, RTM_NEWADDR, &mut payloads).unwrap());
+        assert_eq!(payloads, [payload.as_slice()]);
+
+        datagram.extend(message(NLMSG_DONE, &[0; 4]));
+        datagram.extend(message(RTM_NEWADDR, &payload));
+        let mut payloads = Vec::new();
+        assert!(collect_replies(&datagram, RTM_NEWADDR, &mut payloads).unwrap());
+        assert_eq!(payloads.len(), 1);
+
+        // An acknowledgement, then an error carrying -EPERM
+        let mut ack = message(NLMSG_ERROR, &[0; 20]);
+        assert!(!collect_replies(&ack, RTM_NEWADDR, &mut Vec::new()).unwrap());
+        let mut error = (-libc::EPERM).to_ne_bytes().to_vec();
+        error.extend([0; 16]);
+        ack.extend(message(NLMSG_ERROR, &error));
+        let err = collect_replies(&ack, RTM_NEWADDR, &mut Vec::new()).unwrap_err();
+        assert_eq!(err.raw_os_error(), Some(libc::EPERM));
+    }
+}
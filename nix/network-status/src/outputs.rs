@@ -0,0 +1,203 @@
+//! Every screen the status is shown on
+//!
+//! Screens are discovered per backend in the configured order: every
+//! connected connector of every DRM card, and every `/dev/fb*`. A device that
+//! an earlier backend already draws on is skipped, so a DRM card is not drawn
+//! on a second time through its fbdev emulation. `update` is called whenever
+//! something may have changed; it opens screens that appeared and drops the
+//! ones that went away, each screen keeping its own layout. Framebuffers that
+//! changed their mode or were replaced by another driver are opened again.
+
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+use crate::config::{Backend, Config};
+use crate::font::Font;
+use crate::rotation::Rotation;
+use crate::{drm, open_fbdev, DisplayState, FramebufferConfig, FramebufferState, Surface};
+
+/// Used by `debug-fb` and watched for when no framebuffer is configured
+pub const DEFAULT_FRAMEBUFFER: &str = "/dev/fb0";
+
+const FB_DIR: &str = "/dev";
+const GRAPHICS_CLASS: &str = "/sys/class/graphics";
+
+pub struct Outputs {
+    /// Every DRM device that is there, kept open until it goes away so hotplug
+    /// checks use the connector status the kernel keeps instead of probing
+    cards: Vec<drm::Card>,
+    screens: Vec<FramebufferState>,
+    /// Shared by every screen, so glyphs are only rasterised once
+    font: Rc<Font>,
+}
+
+impl Outputs {
+    pub fn new(font: Rc<Font>) -> Self {
+        Outputs { cards: Vec::new(), screens: Vec::new(), font }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.screens.is_empty()
+    }
+
+    /// Let go of every screen and of DRM master, e.g. before switching to
+    /// another VT
+    pub fn clear(&mut self) {
+        self.screens.clear();
+        for card in &mut self.cards {
+            card.set_master(false);
+        }
+    }
+
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = &mut FramebufferState> {
+        self.screens.iter_mut()
+    }
+
+    /// Bring the set of screens in line with the hardware, new screens are
+    /// rendered with `state` right away
+    pub fn update(&mut self, config: &Config, state: &DisplayState) {
+        // Devices drawn on by an earlier backend, as their sysfs path
+        let mut claimed = Vec::new();
+        for backend in &config.backends {
+            match backend {
+                Backend::Drm => self.update_drm(config, state, &mut claimed),
+                Backend::Fbdev => self.update_fbdev(config, state, &mut claimed),
+            }
+        }
+
+        // Don't hold on to DRM master for cards we don't draw on
+        for card in &mut self.cards {
+            card.set_master(self.screens.iter().any(|s| on_card(s, &card.path)));
+        }
+    }
+
+    fn update_drm(&mut self, config: &Config, state: &DisplayState, claimed: &mut Vec<PathBuf>) {
+        let paths = match &config.drm_device {
+            Some(path) => vec![path.clone()],
+            None => drm::cards().unwrap_or_default(),
+        };
+
+        // Cards that were unplugged
+        self.screens
+            .retain(|s| !matches!(&s.surface, Surface::Drm(o) if !paths.contains(&o.card)));
+        self.cards.retain(|card| paths.contains(&card.path));
+
+        for path in paths {
+            if !self.cards.iter().any(|card| card.path == path) {
+                match drm::Card::open(&path) {
+                    Ok(card) => self.cards.push(card),
+                    Err(_) => continue,
+                }
+            }
+            let index = self.cards.iter().position(|card| card.path == path).unwrap();
+            let card = &mut self.cards[index];
+
+            let device = card.sysfs_device();
+            let connected = match card.connected() {
+                Ok(connected) if !is_claimed(claimed, device.as_deref()) => connected,
+                // Gone, or shown by another backend
+                _ => Vec::new(),
+            };
+
+            self.screens.retain(|s| match &s.surface {
+                Surface::Drm(o) => o.card != path || connected.contains(&o.connector_id),
+                _ => true,
+            });
+
+            for connector_id in connected {
+                let on_this_card = || self.screens.iter().filter_map(|s| match &s.surface {
+                    Surface::Drm(o) if o.card == path => Some(o),
+                    _ => None,
+                });
+                if on_this_card().any(|o| o.connector_id == connector_id) {
+                    continue;
+                }
+                let busy_crtcs: Vec<u32> = on_this_card().map(|o| o.crtc_id).collect();
+                card.set_master(true);
+                if let Ok(output) = card.output(connector_id, &busy_crtcs) {
+                    let fb_config = output.framebuffer_config();
+                    let font = self.font.clone();
+                    self.screens.push(open_screen(Surface::Drm(output), fb_config, config, state, font));
+                }
+            }
+
+            if self.screens.iter().any(|s| on_card(s, &path)) {
+                claimed.extend(device);
+            }
+        }
+    }
+
+    fn update_fbdev(&mut self, config: &Config, state: &DisplayState, claimed: &mut Vec<PathBuf>) {
+        let paths = match &config.framebuffer {
+            Some(path) => vec![path.clone()],
+            None => framebuffers(),
+        };
+        let usable: Vec<PathBuf> = paths
+            .into_iter()
+            .filter(|path| path.exists() && !is_claimed(claimed, fb_sysfs_device(path).as_deref()))
+            .collect();
+
+        // Gone, or changed mode or driver and opened again below
+        self.screens.retain(|s| match &s.surface {
+            Surface::Fbdev { path, .. } => usable.contains(path) && !s.surface.is_stale(),
+            _ => true,
+        });
+
+        for path in &usable {
+            let open = self.screens.iter().any(|s| matches!(&s.surface, Surface::Fbdev { path: p, .. } if p == path));
+            if !open {
+                match open_fbdev(path) {
+                    Ok((surface, fb_config)) => {
+                        self.screens.push(open_screen(surface, fb_config, config, state, self.font.clone()));
+                    }
+                    Err(_) => continue,
+                }
+            }
+            claimed.extend(fb_sysfs_device(path));
+        }
+    }
+}
+
+/// Lay out a new screen and draw `state` on it
+fn open_screen(
+    surface: Surface,
+    mut fb_config: FramebufferConfig,
+    config: &Config,
+    state: &DisplayState,
+    font: Rc<Font>,
+) -> FramebufferState {
+    fb_config.rotation = Rotation::pick(config.rotate, fb_config.rotation);
+    let mut screen = FramebufferState::new(surface, fb_config, state, config, font);
+    screen.render(state);
+    screen
+}
+
+fn on_card(screen: &FramebufferState, card: &Path) -> bool {
+    matches!(&screen.surface, Surface::Drm(o) if o.card == card)
+}
+
+fn is_claimed(claimed: &[PathBuf], device: Option<&Path>) -> bool {
+    device.is_some_and(|device| claimed.iter().any(|c| c == device))
+}
+
+/// `/dev/fb*`, sorted by number
+fn framebuffers() -> Vec<PathBuf> {
+    let Ok(entries) = std::fs::read_dir(FB_DIR) else {
+        return Vec::new();
+    };
+    let mut framebuffers: Vec<(u32, PathBuf)> = entries
+        .filter_map(|entry| entry.ok())
+        .filter_map(|entry| {
+            let number = entry.file_name().to_str()?.strip_prefix("fb")?.parse().ok()?;
+            Some((number, entry.path()))
+        })
+        .collect();
+    framebuffers.sort();
+    framebuffers.into_iter().map(|(_, path)| path).collect()
+}
+
+/// The device behind a framebuffer in sysfs, `None` for purely virtual ones
+fn fb_sysfs_device(path: &Path) -> Option<PathBuf> {
+    let name = path.file_name()?;
+    std::fs::canonicalize(Path::new(GRAPHICS_CLASS).join(name).join("device")).ok()
+}
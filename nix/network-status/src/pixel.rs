@@ -0,0 +1,198 @@
+//! Packing colours into framebuffer pixels
+//!
+//! Pixel layouts are described by the kernel as one bitfield per channel
+//! (offset and length in bits within the pixel, plus `msb_right`). Pixels are
+//! stored in host byte order, so e.g. RGB565 is `rrrrrggg gggbbbbb` as a
+//! 16-bit integer, not two independent bytes.
+
+use crate::Color;
+
+/// Position of one colour channel inside a pixel
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Channel {
+    pub offset: u32,
+    pub length: u32,
+    /// Bit order within the channel is reversed (most significant bit right)
+    pub msb_right: bool,
+}
+
+impl Channel {
+    pub const fn new(offset: u32, length: u32) -> Self {
+        Channel { offset, length, msb_right: false }
+    }
+
+    fn max(&self) -> u32 {
+        if self.length >= 32 {
+            u32::MAX
+        } else {
+            (1u32 << self.length) - 1
+        }
+    }
+
+    /// Scale an 8-bit value to this channel and shift it into place
+    fn pack(&self, value: u8) -> u32 {
+        if self.length == 0 || self.offset >= 32 {
+            return 0;
+        }
+        let max = self.max() as u64;
+        let mut scaled = ((value as u64 * max + 127) / 255) as u32;
+        if self.msb_right {
+            scaled = scaled.reverse_bits() >> (32 - self.length.min(32));
+        }
+        scaled.checked_shl(self.offset).unwrap_or(0)
+    }
+
+    /// Extract this channel from a pixel and scale it back to 8 bits
+    fn unpack(&self, pixel: u32) -> u8 {
+        if self.length == 0 || self.offset >= 32 {
+            return 0;
+        }
+        let max = self.max();
+        let mut raw = (pixel >> self.offset) & max;
+        if self.msb_right {
+            raw = raw.reverse_bits() >> (32 - self.length.min(32));
+        }
+        ((raw as u64 * 255 + max as u64 / 2) / max as u64) as u8
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PixelFormat {
+    pub bytes_per_pixel: usize,
+    pub red: Channel,
+    pub green: Channel,
+    pub blue: Channel,
+    /// Set to fully opaque on every write, `length == 0` if there is none
+    pub alpha: Channel,
+}
+
+impl PixelFormat {
+    /// Pack `color` into a pixel value
+    pub fn pack(&self, color: Color) -> u32 {
+        self.red.pack(color.r) | self.green.pack(color.g) | self.blue.pack(color.b) | self.alpha.pack(0xFF)
+    }
+
+    pub fn unpack(&self, pixel: u32) -> Color {
+        Color {
+            r: self.red.unpack(pixel),
+            g: self.green.unpack(pixel),
+            b: self.blue.unpack(pixel),
+        }
+    }
+
+    /// The colour `color` becomes after a round trip through this format
+    #[cfg(test)]
+    pub fn quantize(&self, color: Color) -> Color {
+        self.unpack(self.pack(color))
+    }
+
+    /// Write `color` into `dst`, which must be at least `bytes_per_pixel` long
+    pub fn write(&self, dst: &mut [u8], color: Color) {
+        let bytes = self.pack(color).to_ne_bytes();
+        dst[..self.bytes_per_pixel].copy_from_slice(self.native_bytes(&bytes));
+    }
+
+    /// Read the pixel at the start of `src`
+    pub fn read(&self, src: &[u8]) -> Color {
+        let mut bytes = [0u8; 4];
+        let len = self.bytes_per_pixel;
+        if cfg!(target_endian = "little") {
+            bytes[..len].copy_from_slice(&src[..len]);
+        } else {
+            bytes[4 - len..].copy_from_slice(&src[..len]);
+        }
+        self.unpack(u32::from_ne_bytes(bytes))
+    }
+
+    /// The bytes of a native endian u32 that make up a pixel of our size
+    fn native_bytes<'a>(&self, bytes: &'a [u8; 4]) -> &'a [u8] {
+        if cfg!(target_endian = "little") {
+            &bytes[..self.bytes_per_pixel]
+        } else {
+            &bytes[4 - self.bytes_per_pixel..]
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RGB565: PixelFormat = PixelFormat {
+        bytes_per_pixel: 2,
+        red: Channel::new(11, 5),
+        green: Channel::new(5, 6),
+        blue: Channel::new(0, 5),
+        alpha: Channel::new(0, 0),
+    };
+
+    #[test]
+    fn rgb565_packing() {
+        assert_eq!(RGB565.pack(Color { r: 255, g: 255, b: 255 }), 0xFFFF);
+        assert_eq!(RGB565.pack(Color { r: 255, g: 0, b: 0 }), 0xF800);
+        assert_eq!(RGB565.pack(Color { r: 0, g: 255, b: 0 }), 0x07E0);
+        assert_eq!(RGB565.pack(Color { r: 0, g: 0, b: 255 }), 0x001F);
+        assert_eq!(RGB565.pack(Color { r: 0, g: 0, b: 0 }), 0x0000);
+
+        let mut buf = [0u8; 2];
+        RGB565.write(&mut buf, Color { r: 255, g: 0, b: 0 });
+        assert_eq!(u16::from_ne_bytes(buf), 0xF800);
+    }
+
+    #[test]
+    fn alpha_is_opaque_wherever_it_lives() {
+        let argb = PixelFormat {
+            bytes_per_pixel: 4,
+            red: Channel::new(8, 8),
+            green: Channel::new(16, 8),
+            blue: Channel::new(24, 8),
+            alpha: Channel::new(0, 8),
+        };
+        assert_eq!(argb.pack(Color { r: 0, g: 0, b: 0 }), 0x0000_00FF);
+        assert_eq!(argb.pack(Color { r: 0x12, g: 0x34, b: 0x56 }), 0x5634_12FF);
+
+        let xrgb = PixelFormat { alpha: Channel::new(0, 0), ..argb };
+        assert_eq!(xrgb.pack(Color { r: 0x12, g: 0x34, b: 0x56 }), 0x5634_1200);
+
+        let argb1555 = PixelFormat {
+            bytes_per_pixel: 2,
+            red: Channel::new(10, 5),
+            green: Channel::new(5, 5),
+            blue: Channel::new(0, 5),
+            alpha: Channel::new(15, 1),
+        };
+        assert_eq!(argb1555.pack(Color { r: 0, g: 0, b: 0 }), 0x8000);
+    }
+
+    #[test]
+    fn msb_right_reverses_bits() {
+        let format = PixelFormat {
+            bytes_per_pixel: 2,
+            red: Channel { offset: 11, length: 5, msb_right: true },
+            green: Channel::new(5, 6),
+            blue: Channel::new(0, 5),
+            alpha: Channel::new(0, 0),
+        };
+        // 0b00001 reversed is 0b10000
+        assert_eq!(format.pack(Color { r: 8, g: 0, b: 0 }), 0b10000 << 11);
+        let color = Color { r: 132, g: 0, b: 0 };
+        assert_eq!(format.quantize(color), RGB565.quantize(color));
+    }
+
+    #[test]
+    fn round_trip_is_exact_for_8_bit_channels() {
+        let format = PixelFormat {
+            bytes_per_pixel: 3,
+            red: Channel::new(16, 8),
+            green: Channel::new(8, 8),
+            blue: Channel::new(0, 8),
+            alpha: Channel::new(0, 0),
+        };
+        for v in 0..=255u8 {
+            let color = Color { r: v, g: 255 - v, b: v / 2 };
+            let mut buf = [0u8; 3];
+            format.write(&mut buf, color);
+            assert_eq!(format.read(&buf), color);
+        }
+    }
+}
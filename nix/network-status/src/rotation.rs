@@ -0,0 +1,136 @@
+//! Drawing on rotated panels
+//!
+//! Tablets, portrait kiosk panels and some BMC consoles are mounted at an
+//! angle and the kernel console is rotated to match. We follow the same
+//! setting: the layout is computed for the rotated screen and every pixel is
+//! moved to its place in the framebuffer as it is written.
+//!
+//! The rotation is, in order of preference: `--rotate` or `rotate` in the
+//! config, the framebuffer's own `rotate`, the console's current rotation in
+//! sysfs, and `fbcon=rotate:` on the kernel command line.
+
+use std::path::Path;
+
+const FBCON_ROTATE: &str = "/sys/class/graphics/fbcon/rotate";
+const CMDLINE: &str = "/proc/cmdline";
+
+/// Clockwise rotation of the picture, like the kernel's `FB_ROTATE_*`
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Rotation {
+    #[default]
+    Normal,
+    Clockwise,
+    UpsideDown,
+    CounterClockwise,
+}
+
+impl Rotation {
+    pub const NAMES: &'static str = "0, 90, 180 or 270";
+
+    pub fn from_degrees(degrees: &str) -> Option<Self> {
+        match degrees {
+            "0" => Some(Rotation::Normal),
+            "90" => Some(Rotation::Clockwise),
+            "180" => Some(Rotation::UpsideDown),
+            "270" => Some(Rotation::CounterClockwise),
+            _ => None,
+        }
+    }
+
+    pub fn degrees(self) -> u32 {
+        match self {
+            Rotation::Normal => 0,
+            Rotation::Clockwise => 90,
+            Rotation::UpsideDown => 180,
+            Rotation::CounterClockwise => 270,
+        }
+    }
+
+    /// `FB_ROTATE_UR`, `_CW`, `_UD` and `_CCW`, as used by `fbcon=rotate:`
+    /// and `fb_var_screeninfo.rotate`
+    pub fn from_kernel(value: u32) -> Option<Self> {
+        match value {
+            0 => Some(Rotation::Normal),
+            1 => Some(Rotation::Clockwise),
+            2 => Some(Rotation::UpsideDown),
+            3 => Some(Rotation::CounterClockwise),
+            _ => None,
+        }
+    }
+
+    /// `fbcon=rotate:<n>` on a kernel command line, the last one wins
+    fn from_cmdline(cmdline: &str) -> Option<Self> {
+        cmdline
+            .split_whitespace()
+            .filter_map(|arg| arg.strip_prefix("fbcon="))
+            .flat_map(|options| options.split(','))
+            .filter_map(|option| option.strip_prefix("rotate:"))
+            .filter_map(|n| n.parse().ok().and_then(Rotation::from_kernel))
+            .next_back()
+    }
+
+    /// The rotation to draw with, `forced` by the user or `device`'s own, else
+    /// the console's
+    pub fn pick(forced: Option<Rotation>, device: Rotation) -> Self {
+        let read = |path: &str| std::fs::read_to_string(Path::new(path)).ok();
+        forced
+            .or((device != Rotation::Normal).then_some(device))
+            .or_else(|| read(FBCON_ROTATE).and_then(|s| s.trim().parse().ok()).and_then(Rotation::from_kernel))
+            .or_else(|| read(CMDLINE).and_then(|s| Rotation::from_cmdline(&s)))
+            .unwrap_or_default()
+    }
+
+    /// Size of the picture on a `width` x `height` framebuffer
+    pub fn screen_size(self, width: usize, height: usize) -> (usize, usize) {
+        match self {
+            Rotation::Normal | Rotation::UpsideDown => (width, height),
+            Rotation::Clockwise | Rotation::CounterClockwise => (height, width),
+        }
+    }
+
+    /// Framebuffer position of the picture's pixel at `(x, y)`, on a
+    /// framebuffer `width` x `height` pixels large
+    ///
+    /// The picture must be `screen_size` large and `(x, y)` inside it.
+    pub fn to_framebuffer(self, x: usize, y: usize, width: usize, height: usize) -> (usize, usize) {
+        match self {
+            Rotation::Normal => (x, y),
+            // The top of the picture is on the right
+            Rotation::Clockwise => (width - 1 - y, x),
+            Rotation::UpsideDown => (width - 1 - x, height - 1 - y),
+            Rotation::CounterClockwise => (y, height - 1 - x),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn names_and_kernel_values() {
+        assert_eq!(Rotation::from_degrees("270"), Some(Rotation::CounterClockwise));
+        assert_eq!(Rotation::from_degrees("45"), None);
+        assert_eq!(Rotation::from_kernel(1), Some(Rotation::Clockwise));
+        assert_eq!(Rotation::from_kernel(4), None);
+        assert_eq!(Rotation::from_cmdline("quiet fbcon=font:TER16x32,rotate:1 ro"), Some(Rotation::Clockwise));
+        assert_eq!(Rotation::from_cmdline("fbcon=rotate:3 fbcon=rotate:2"), Some(Rotation::UpsideDown));
+        assert_eq!(Rotation::from_cmdline("fbcon=rotate:9 console=ttyS0"), None);
+        assert_eq!(Rotation::pick(Some(Rotation::Normal), Rotation::Clockwise), Rotation::Normal);
+        assert_eq!(Rotation::pick(None, Rotation::UpsideDown), Rotation::UpsideDown);
+    }
+
+    #[test]
+    fn corners_land_on_corners() {
+        // A 4x2 framebuffer
+        let corners = |rotation: Rotation| {
+            let (w, h) = rotation.screen_size(4, 2);
+            [(0, 0), (w - 1, 0), (0, h - 1)].map(|(x, y)| rotation.to_framebuffer(x, y, 4, 2))
+        };
+        // Top left, top right and bottom left of the picture
+        assert_eq!(corners(Rotation::Normal), [(0, 0), (3, 0), (0, 1)]);
+        assert_eq!(corners(Rotation::Clockwise), [(3, 0), (3, 1), (0, 0)]);
+        assert_eq!(corners(Rotation::UpsideDown), [(3, 1), (0, 1), (3, 0)]);
+        assert_eq!(corners(Rotation::CounterClockwise), [(0, 1), (0, 0), (3, 1)]);
+    }
+}
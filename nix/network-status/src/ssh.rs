@@ -0,0 +1,115 @@
+//! Details about the local SSH server: host keys and listening port
+
+use std::path::Path;
+
+use base64::engine::general_purpose::{STANDARD, STANDARD_NO_PAD};
+use base64::Engine;
+use sha2::{Digest, Sha256};
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct HostKey {
+    /// Key type as it appears in the public key file, e.g. `ssh-ed25519`
+    pub algorithm: String,
+    /// `<type> <base64>` without the comment, ready for a known_hosts entry
+    pub public_key: String,
+    /// Fingerprint in the format printed by `ssh-keygen -l`, e.g. `SHA256:...`
+    pub fingerprint: String,
+}
+
+impl HostKey {
+    /// Parse a single line in OpenSSH public key format (`<type> <base64> [comment]`)
+    pub fn parse(line: &str) -> Option<Self> {
+        let mut parts = line.split_whitespace();
+        let algorithm = parts.next()?.to_string();
+        let encoded = parts.next()?;
+        let blob = STANDARD.decode(encoded).ok()?;
+
+        let digest = Sha256::digest(&blob);
+        let fingerprint = format!("SHA256:{}", STANDARD_NO_PAD.encode(digest));
+        let public_key = format!("{} {}", algorithm, encoded);
+
+        Some(HostKey { algorithm, public_key, fingerprint })
+    }
+
+    /// Key type as shown by `ssh-keygen -l`, e.g. `ED25519`
+    pub fn short_name(&self) -> &str {
+        match self.algorithm.as_str() {
+            "ssh-ed25519" => "ED25519",
+            "ssh-rsa" => "RSA",
+            "ssh-dss" => "DSA",
+            a if a.starts_with("ecdsa-sha2-") => "ECDSA",
+            a if a.starts_with("sk-ssh-ed25519") => "ED25519-SK",
+            a if a.starts_with("sk-ecdsa-sha2-") => "ECDSA-SK",
+            a => a,
+        }
+    }
+}
+
+/// Read all `ssh_host_*_key.pub` files in `dir`, sorted by file name
+pub fn read_host_keys(dir: &Path) -> Vec<HostKey> {
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return Vec::new();
+    };
+
+    let mut paths: Vec<_> = entries
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter(|path| {
+            path.file_name()
+                .and_then(|name| name.to_str())
+                .is_some_and(|name| name.starts_with("ssh_host_") && name.ends_with("_key.pub"))
+        })
+        .collect();
+    paths.sort();
+
+    paths
+        .iter()
+        .filter_map(|path| std::fs::read_to_string(path).ok())
+        .filter_map(|contents| HostKey::parse(contents.trim()))
+        .collect()
+}
+
+/// Port sshd listens on according to the first `Port` directive in `sshd_config`
+pub fn read_ssh_port(sshd_config: &Path) -> u16 {
+    std::fs::read_to_string(sshd_config)
+        .ok()
+        .and_then(|contents| {
+            contents.lines().find_map(|line| {
+                let mut parts = line.split_whitespace();
+                match parts.next() {
+                    Some(keyword) if keyword.eq_ignore_ascii_case("port") => parts.next()?.parse().ok(),
+                    _ => None,
+                }
+            })
+        })
+        .unwrap_or(22)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fingerprint_matches_ssh_keygen() {
+        let key = HostKey::parse(
+            "ssh-ed25519 AAAAC3NzaC1lZDI1NTE5AAAAIDzCT24io+TsVkWFGzRlDhD9vRF3/9GmraL17e/ff5bC root@nixos",
+        )
+        .unwrap();
+
+        assert_eq!(key.algorithm, "ssh-ed25519");
+        assert_eq!(key.short_name(), "ED25519");
+        assert_eq!(
+            key.public_key,
+            "ssh-ed25519 AAAAC3NzaC1lZDI1NTE5AAAAIDzCT24io+TsVkWFGzRlDhD9vRF3/9GmraL17e/ff5bC"
+        );
+        // ssh-keygen -lf
+        assert_eq!(key.fingerprint, "SHA256:iLpeOnMjDsXBVPVJloQt5RLHH9S0iKWJl/dBnej1PMI");
+    }
+
+    #[test]
+    fn invalid_keys() {
+        assert_eq!(HostKey::parse(""), None);
+        assert_eq!(HostKey::parse("ssh-ed25519"), None);
+        assert_eq!(HostKey::parse("ssh-ed25519 not-base64!"), None);
+    }
+}
@@ -0,0 +1,202 @@
+//! Measuring and wrapping text on the character grid
+//!
+//! Text is drawn in the cells of a monospaced font. The unit is the grapheme
+//! cluster, not the byte or `char`: a letter with combining accents takes one
+//! cell, East Asian wide characters and most emoji take two. Rows are wrapped
+//! after whitespace, tokens longer than a row (e.g. IPv6 addresses) are
+//! broken between clusters.
+
+use unicode_segmentation::UnicodeSegmentation;
+use unicode_width::UnicodeWidthStr;
+
+use crate::TextSegment;
+
+/// Grapheme clusters of `text` with their width in cells
+pub fn clusters(text: &str) -> impl Iterator<Item = (&str, usize)> {
+    // Every cluster gets a cell, control characters are drawn as a box
+    text.graphemes(true).map(|cluster| (cluster, cluster.width().max(1)))
+}
+
+/// Cells taken by `text`
+pub fn width(text: &str) -> usize {
+    clusters(text).map(|(_, width)| width).sum()
+}
+
+/// Cells taken by `segments`
+pub fn segments_width(segments: &[TextSegment]) -> usize {
+    segments.iter().map(|s| width(&s.text)).sum()
+}
+
+#[derive(Clone, Copy)]
+struct Cluster<'a> {
+    text: &'a str,
+    /// The segment it is from, for its attributes
+    segment: &'a TextSegment,
+    width: usize,
+}
+
+impl Cluster<'_> {
+    fn is_whitespace(&self) -> bool {
+        self.text.chars().all(char::is_whitespace)
+    }
+}
+
+/// Break `segments` into rows of at most `max_width` cells
+///
+/// Rows break after whitespace, a word is only split if it wouldn't fit on a
+/// row of its own either. Continuation rows are indented by `indent` spaces
+/// and don't start with whitespace. A cluster wider than `max_width` still
+/// gets a row of its own.
+pub fn wrap(segments: &[TextSegment], max_width: usize, indent: usize) -> Vec<Vec<TextSegment>> {
+    let max_width = max_width.max(1);
+    let indent = indent.min(max_width - 1);
+    let clusters: Vec<Cluster> = segments
+        .iter()
+        .flat_map(|s| clusters(&s.text).map(move |(text, width)| Cluster { text, segment: s, width }))
+        .collect();
+    let has_content = |row: &[Cluster]| row.iter().any(|c| !c.is_whitespace());
+
+    let mut rows = Vec::new();
+    let mut row: Vec<Cluster> = Vec::new();
+    let mut used = 0;
+    for word in words(&clusters) {
+        let body_len = word.iter().take_while(|c| !c.is_whitespace()).count();
+        let (body, space) = word.split_at(body_len);
+        let body_width: usize = body.iter().map(|c| c.width).sum();
+
+        // Start a new row, unless the word is too long for that one as well
+        if has_content(&row) && used + body_width > max_width && indent + body_width <= max_width {
+            rows.push(std::mem::take(&mut row));
+            used = indent;
+        }
+        for &cluster in body {
+            if has_content(&row) && used + cluster.width > max_width {
+                rows.push(std::mem::take(&mut row));
+                used = indent;
+            }
+            row.push(cluster);
+            used += cluster.width;
+        }
+        // Whitespace at the end of a row is dropped, so it may overflow
+        row.extend_from_slice(space);
+        used += space.iter().map(|c| c.width).sum::<usize>();
+    }
+    if has_content(&row) || rows.is_empty() {
+        rows.push(row);
+    }
+
+    rows.into_iter()
+        .enumerate()
+        .map(|(i, row)| to_segments(&row, if i == 0 { 0 } else { indent }))
+        .collect()
+}
+
+/// Split after every run of whitespace, so each word is a body followed by
+/// the whitespace after it
+fn words<'a, 'b>(clusters: &'b [Cluster<'a>]) -> Vec<&'b [Cluster<'a>]> {
+    let mut words = Vec::new();
+    let mut start = 0;
+    for i in 1..clusters.len() {
+        if clusters[i - 1].is_whitespace() && !clusters[i].is_whitespace() {
+            words.push(&clusters[start..i]);
+            start = i;
+        }
+    }
+    if start < clusters.len() {
+        words.push(&clusters[start..]);
+    }
+    words
+}
+
+/// Merge a row of clusters back into segments, without trailing whitespace
+fn to_segments(row: &[Cluster], indent: usize) -> Vec<TextSegment> {
+    let visible = row.len() - row.iter().rev().take_while(|c| c.is_whitespace()).count();
+    let mut segments: Vec<TextSegment> = Vec::new();
+    if indent > 0 {
+        if let Some(first) = row.first() {
+            segments.push(TextSegment::new(" ".repeat(indent), first.segment.color));
+        }
+    }
+    for cluster in &row[..visible] {
+        match segments.last_mut() {
+            Some(last) if last.same_style(cluster.segment) => last.text.push_str(cluster.text),
+            _ => segments.push(cluster.segment.with_text(cluster.text)),
+        }
+    }
+    segments
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Color;
+
+    const WHITE: Color = Color { r: 255, g: 255, b: 255 };
+    const RED: Color = Color { r: 255, g: 0, b: 0 };
+
+    fn segments(parts: &[&str]) -> Vec<TextSegment> {
+        parts.iter().map(|p| TextSegment::new(*p, WHITE)).collect()
+    }
+
+    fn text(rows: Vec<Vec<TextSegment>>) -> Vec<String> {
+        rows.into_iter().map(|r| r.into_iter().map(|s| s.text).collect()).collect()
+    }
+
+    #[test]
+    fn width_counts_cells() {
+        assert_eq!(width("eth0"), 4);
+        // Multibyte, but one cell each
+        assert_eq!(width("Grüße"), 5);
+        // Decomposed: e and a combining acute accent
+        assert_eq!(width("e\u{301}"), 1);
+        assert_eq!(width("漢字"), 4);
+        assert_eq!(width("\u{1b}"), 1);
+        assert_eq!(clusters("ñ̃a").map(|(c, _)| c).collect::<Vec<_>>(), ["ñ̃", "a"]);
+    }
+
+    #[test]
+    fn wrap_breaks_after_whitespace() {
+        assert_eq!(text(wrap(&segments(&["eth0 ", "UP ", "192.0.2.2"]), 12, 2)), ["eth0 UP", "  192.0.2.2"]);
+        assert_eq!(text(wrap(&segments(&["short"]), 20, 2)), ["short"]);
+        // Words can span segments, e.g. a coloured address and its prefix length
+        assert_eq!(text(wrap(&segments(&["a ", "192.0.2.2", "/24"]), 12, 0)), ["a", "192.0.2.2/24"]);
+        // Trailing whitespace doesn't push a word to the next row
+        assert_eq!(text(wrap(&segments(&["ab cd  ", "ef"]), 5, 0)), ["ab cd", "ef"]);
+        assert_eq!(text(wrap(&segments(&["a bcdef"]), 4, 1)), ["a bc", " def"]);
+        assert_eq!(text(wrap(&[], 4, 0)), [""]);
+    }
+
+    #[test]
+    fn wrap_breaks_long_tokens() {
+        assert_eq!(text(wrap(&segments(&["abcdefghij"]), 4, 0)), ["abcd", "efgh", "ij"]);
+        assert_eq!(
+            text(wrap(&segments(&["inet6 2001:db8:85a3::8a2e:370:7334/64"]), 16, 2)),
+            ["inet6 2001:db8:8", "  5a3::8a2e:370:", "  7334/64"]
+        );
+    }
+
+    #[test]
+    fn wrap_measures_clusters() {
+        // Three cells, although six chars and nine bytes
+        assert_eq!(text(wrap(&segments(&["e\u{301}e\u{301}e\u{301} x"]), 5, 0)), ["e\u{301}e\u{301}e\u{301} x"]);
+        // Wide characters are never split across rows
+        assert_eq!(text(wrap(&segments(&["漢字漢字"]), 5, 0)), ["漢字", "漢字"]);
+        assert_eq!(text(wrap(&segments(&["漢"]), 1, 0)), ["漢"]);
+    }
+
+    #[test]
+    fn wrap_keeps_colors() {
+        let input = vec![
+            TextSegment::new("eth0 ", WHITE),
+            TextSegment::new("192.0.2.2", RED),
+            TextSegment::new("/24", WHITE),
+        ];
+        let rows = wrap(&input, 10, 2);
+        let red = |text: &str| TextSegment::new(text, RED);
+        let white = |text: &str| TextSegment::new(text, WHITE);
+        assert_eq!(rows, [vec![white("eth0 "), red("192.0")], vec![red("  .2.2"), white("/24")]]);
+        for row in &rows {
+            assert!(segments_width(row) <= 10);
+        }
+    }
+}
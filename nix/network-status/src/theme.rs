@@ -0,0 +1,245 @@
+//! Colours, texts and the logo
+//!
+//! The built-in theme is Clan's. A theme file, named by `theme` in the
+//! config or `--theme`, replaces any part of it. It uses the same TOML subset
+//! as the config:
+//!
+//! ```toml
+//! background = "#000000"
+//! foreground = "#D3D7CF"  # text without an ANSI colour
+//! accent = "#FFFFFF"  # section titles
+//! separator = "#646464"  # line above the footer
+//! logo = "logo.svg"  # PNG, SVG, or raw RGBA with logo_width and logo_height, "" for none
+//! logo_width = 223
+//! logo_height = 89
+//! logo_background = "#FFFFFF"  # "" to draw the logo straight on the background
+//! footer = "Press 'Ctrl-C' for console access"
+//! credentials_title = "Login Credentials"
+//! network_title = "Network Information"
+//! remote_title = "Remote Access"
+//! host_keys_title = "Host Keys"
+//! ```
+//!
+//! A relative logo path is relative to the theme file.
+
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+use crate::config::{self, ConfigError, Value};
+use crate::logo::Logo;
+use crate::{ansi, Color};
+
+/// Section titles, on screen and in the terminal
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Titles {
+    pub credentials: String,
+    pub network: String,
+    pub remote: String,
+    pub host_keys: String,
+}
+
+#[derive(Clone, Debug)]
+pub struct Theme {
+    pub background: Color,
+    /// Text without an ANSI colour
+    pub foreground: Color,
+    /// Section titles
+    pub accent: Color,
+    /// The line above the footer
+    pub separator: Color,
+    /// `None` to show no logo
+    pub logo: Option<Rc<Logo>>,
+    /// Behind the logo and its padding, `None` for none
+    pub logo_background: Option<Color>,
+    pub footer: String,
+    pub titles: Titles,
+}
+
+impl Default for Theme {
+    fn default() -> Self {
+        Theme {
+            background: Color { r: 0, g: 0, b: 0 },
+            foreground: ansi::PALETTE[7],
+            accent: Color { r: 255, g: 255, b: 255 },
+            separator: Color { r: 100, g: 100, b: 100 },
+            logo: Some(Rc::new(Logo::built_in())),
+            logo_background: Some(Color { r: 255, g: 255, b: 255 }),
+            footer: "Press 'Ctrl-C' for console access".to_string(),
+            titles: Titles {
+                credentials: "Login Credentials".to_string(),
+                network: "Network Information".to_string(),
+                remote: "Remote Access".to_string(),
+                host_keys: "Host Keys".to_string(),
+            },
+        }
+    }
+}
+
+impl Theme {
+    pub fn load(path: &Path) -> Result<Self, ConfigError> {
+        let contents = std::fs::read_to_string(path).map_err(|e| ConfigError::Io(path.to_path_buf(), e))?;
+        let mut theme = Theme::default();
+        let mut logo: Option<PathBuf> = None;
+        let mut logo_width = None;
+        let mut logo_height = None;
+
+        config::parse_lines(path, &contents, |key, value| {
+            match (key, value) {
+                ("background", Value::String(s)) => theme.background = parse_color(key, &s)?,
+                ("foreground", Value::String(s)) => theme.foreground = parse_color(key, &s)?,
+                ("accent", Value::String(s)) => theme.accent = parse_color(key, &s)?,
+                ("separator", Value::String(s)) => theme.separator = parse_color(key, &s)?,
+                ("logo_background", Value::String(s)) if s.is_empty() => theme.logo_background = None,
+                ("logo_background", Value::String(s)) => theme.logo_background = Some(parse_color(key, &s)?),
+                ("logo", Value::String(s)) => logo = Some(PathBuf::from(s)),
+                ("logo_width" | "logo_height", Value::Integer(0)) => {
+                    return Err(format!("`{}` must be at least 1", key));
+                }
+                ("logo_width" | "logo_height", Value::Integer(n)) => {
+                    let n = usize::try_from(n).map_err(|_| format!("`{}` is too large", key))?;
+                    match key {
+                        "logo_width" => logo_width = Some(n),
+                        _ => logo_height = Some(n),
+                    }
+                }
+                ("footer", Value::String(s)) => theme.footer = s,
+                ("credentials_title", Value::String(s)) => theme.titles.credentials = s,
+                ("network_title", Value::String(s)) => theme.titles.network = s,
+                ("remote_title", Value::String(s)) => theme.titles.remote = s,
+                ("host_keys_title", Value::String(s)) => theme.titles.host_keys = s,
+                ("logo_width" | "logo_height", _) => return Err(format!("`{}` must be an integer", key)),
+                (
+                    "background" | "foreground" | "accent" | "separator" | "logo_background" | "logo" | "footer"
+                    | "credentials_title" | "network_title" | "remote_title" | "host_keys_title",
+                    _,
+                ) => return Err(format!("`{}` must be a string", key)),
+                _ => return Err(format!("unknown key `{}`", key)),
+            }
+            Ok(())
+        })?;
+
+        let size = match (logo_width, logo_height) {
+            (Some(width), Some(height)) => Some((width, height)),
+            (None, None) => None,
+            _ => {
+                let message = "logo_width and logo_height must be given together".to_string();
+                return Err(ConfigError::Invalid(path.to_path_buf(), message));
+            }
+        };
+        match logo {
+            Some(logo) if logo.as_os_str().is_empty() => theme.logo = None,
+            Some(logo) => {
+                let logo = path.parent().unwrap_or(Path::new("")).join(logo);
+                let loaded = Logo::load(&logo, size).map_err(|e| ConfigError::Io(logo, e))?;
+                theme.logo = Some(Rc::new(loaded));
+            }
+            None if size.is_some() => {
+                let message = "logo_width and logo_height are only used with logo".to_string();
+                return Err(ConfigError::Invalid(path.to_path_buf(), message));
+            }
+            None => {}
+        }
+        Ok(theme)
+    }
+}
+
+/// `#RRGGBB`, the `#` is optional like in `console.colors`
+fn parse_color(key: &str, value: &str) -> Result<Color, String> {
+    let hex = value.strip_prefix('#').unwrap_or(value);
+    match u32::from_str_radix(hex, 16) {
+        Ok(rgb) if hex.len() == 6 && hex.chars().all(|c| c.is_ascii_hexdigit()) => Ok(Color {
+            r: (rgb >> 16) as u8,
+            g: (rgb >> 8) as u8,
+            b: rgb as u8,
+        }),
+        _ => Err(format!("`{}` must be a colour like \"#RRGGBB\", got \"{}\"", key, value)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("network-status-theme-{}-{}", name, std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn theme_file_overrides_defaults() {
+        let dir = temp_dir("overrides");
+        std::fs::write(dir.join("logo.rgba"), [1, 2, 3, 4, 5, 6, 7, 8]).unwrap();
+        let path = dir.join("theme.toml");
+        std::fs::write(
+            &path,
+            "# Acme\n\
+             background = \"#102030\"\n\
+             accent = \"FFAA00\"\n\
+             footer = \"Call Acme support\"\n\
+             network_title = \"Netzwerk\"\n\
+             logo = \"logo.rgba\"  # next to the theme\n\
+             logo_width = 2\n\
+             logo_height = 1\n",
+        )
+        .unwrap();
+
+        let theme = Theme::load(&path).unwrap();
+        assert_eq!(theme.background, Color { r: 0x10, g: 0x20, b: 0x30 });
+        assert_eq!(theme.accent, Color { r: 0xFF, g: 0xAA, b: 0x00 });
+        assert_eq!(theme.foreground, Theme::default().foreground);
+        assert_eq!(theme.footer, "Call Acme support");
+        assert_eq!(theme.titles.network, "Netzwerk");
+        assert_eq!(theme.titles.credentials, "Login Credentials");
+        let logo = theme.logo.unwrap();
+        assert_eq!((logo.width, logo.height), (2, 1));
+        assert_eq!(logo.scaled(2, 1).rgba, [1, 2, 3, 4, 5, 6, 7, 8]);
+
+        std::fs::write(&path, "logo = \"\"\n").unwrap();
+        assert!(Theme::load(&path).unwrap().logo.is_none());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn errors_name_the_problem() {
+        let dir = temp_dir("errors");
+        let path = dir.join("theme.toml");
+        let error = |contents: &str| {
+            std::fs::write(&path, contents).unwrap();
+            let message = Theme::load(&path).unwrap_err().to_string();
+            message.strip_prefix(&format!("{}", path.display())).unwrap().to_string()
+        };
+
+        assert_eq!(error("\n\naccent = \"red\""), ":3: `accent` must be a colour like \"#RRGGBB\", got \"red\"");
+        assert_eq!(error("accent = \"#12345\""), ":1: `accent` must be a colour like \"#RRGGBB\", got \"#12345\"");
+        assert_eq!(error("footer = 1"), ":1: `footer` must be a string");
+        assert_eq!(error("colour = \"#000000\""), ":1: unknown key `colour`");
+        assert_eq!(error("logo_width = 0"), ":1: `logo_width` must be at least 1");
+        assert_eq!(error("logo_width = 2"), ": logo_width and logo_height must be given together");
+
+        std::fs::write(dir.join("logo.rgba"), [0; 7]).unwrap();
+        let message = {
+            std::fs::write(&path, "logo = \"logo.rgba\"\nlogo_width = 2\nlogo_height = 1").unwrap();
+            Theme::load(&path).unwrap_err().to_string()
+        };
+        assert!(message.ends_with("logo.rgba: 7 bytes is not 2x1 RGBA"), "{}", message);
+        std::fs::write(&path, "logo = \"logo.rgba\"").unwrap();
+        assert!(Theme::load(&path).unwrap_err().to_string().contains("not a PNG"));
+        std::fs::write(&path, "logo = \"missing.png\"").unwrap();
+        assert!(Theme::load(&path).unwrap_err().to_string().contains("missing.png"));
+
+        // The size in bytes doesn't fit in memory, nor wraps around to the file's
+        std::fs::write(dir.join("logo.rgba"), []).unwrap();
+        let message = {
+            std::fs::write(&path, "logo = \"logo.rgba\"\nlogo_width = 4611686018427387904\nlogo_height = 1").unwrap();
+            Theme::load(&path).unwrap_err().to_string()
+        };
+        assert!(message.ends_with("logo.rgba: 4611686018427387904x1 RGBA is too large"), "{}", message);
+        if usize::BITS == 32 {
+            assert_eq!(error("logo_width = 4294967296"), ":1: `logo_width` is too large");
+        }
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,102 @@
+//! Kernel uevents for display hotplug
+//!
+//! Connecting a monitor doesn't create a device node, the DRM card only sends
+//! a `change` uevent with `HOTPLUG=1`. We listen on the kernel's uevent netlink
+//! group directly, the same one udev reads.
+
+use std::io;
+use std::os::unix::io::{AsRawFd, FromRawFd, OwnedFd, RawFd};
+
+// From <linux/netlink.h>
+const NETLINK_KOBJECT_UEVENT: libc::c_int = 15;
+const KERNEL_EVENTS_GROUP: u32 = 1;
+
+/// Subsystems whose devices we may draw on
+const SUBSYSTEMS: [&[u8]; 2] = [b"SUBSYSTEM=drm", b"SUBSYSTEM=graphics"];
+
+pub struct Monitor {
+    socket: OwnedFd,
+}
+
+impl Monitor {
+    pub fn new() -> io::Result<Self> {
+        let fd = unsafe {
+            libc::socket(
+                libc::AF_NETLINK,
+                libc::SOCK_DGRAM | libc::SOCK_CLOEXEC | libc::SOCK_NONBLOCK,
+                NETLINK_KOBJECT_UEVENT,
+            )
+        };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let socket = unsafe { OwnedFd::from_raw_fd(fd) };
+
+        let mut addr: libc::sockaddr_nl = unsafe { std::mem::zeroed() };
+        addr.nl_family = libc::AF_NETLINK as libc::sa_family_t;
+        addr.nl_groups = KERNEL_EVENTS_GROUP;
+        let ret = unsafe {
+            libc::bind(
+                socket.as_raw_fd(),
+                &addr as *const libc::sockaddr_nl as *const libc::sockaddr,
+                std::mem::size_of::<libc::sockaddr_nl>() as libc::socklen_t,
+            )
+        };
+        if ret < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        Ok(Monitor { socket })
+    }
+
+    /// Read all pending events, returns true if one was about a display device
+    pub fn drain(&self) -> bool {
+        let mut buf = [0u8; 8192];
+        let mut relevant = false;
+        loop {
+            let len = unsafe {
+                libc::recv(self.socket.as_raw_fd(), buf.as_mut_ptr() as *mut libc::c_void, buf.len(), 0)
+            };
+            if len > 0 {
+                relevant |= is_display_event(&buf[..len as usize]);
+                continue;
+            }
+            match io::Error::last_os_error().raw_os_error() {
+                Some(libc::EINTR) if len < 0 => continue,
+                // Events were lost, one of them may have been ours
+                Some(libc::ENOBUFS) if len < 0 => relevant = true,
+                _ => return relevant,
+            }
+        }
+    }
+}
+
+impl AsRawFd for Monitor {
+    fn as_raw_fd(&self) -> RawFd {
+        self.socket.as_raw_fd()
+    }
+}
+
+/// A uevent is `action@devpath` followed by NUL separated `KEY=value` pairs
+fn is_display_event(event: &[u8]) -> bool {
+    event
+        .split(|&b| b == 0)
+        .skip(1)
+        .any(|field| SUBSYSTEMS.contains(&field))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn matches_display_subsystems_only() {
+        let hotplug = b"change@/devices/pci0000:00/0000:00:02.0/drm/card0\0ACTION=change\0\
+                        DEVPATH=/devices/pci0000:00/0000:00:02.0/drm/card0\0SUBSYSTEM=drm\0HOTPLUG=1\0";
+        assert!(is_display_event(hotplug));
+        assert!(is_display_event(b"add@/devices/virtual/graphics/fb1\0ACTION=add\0SUBSYSTEM=graphics\0"));
+        assert!(!is_display_event(b"add@/devices/usb1/1-1\0ACTION=add\0SUBSYSTEM=usb\0"));
+        // The header is not a key
+        assert!(!is_display_event(b"SUBSYSTEM=drm\0ACTION=add\0"));
+    }
+}
@@ -0,0 +1,195 @@
+//! Sharing the screen with the kernel console
+//!
+//! fbcon keeps drawing the shell and kernel messages onto the framebuffer we
+//! draw on. While we show something, our virtual terminal is switched to
+//! `KD_GRAPHICS`, which makes fbcon leave the screen alone. Switches to other
+//! VTs are handled by us (`VT_PROCESS`): we let go of the screens when asked
+//! to release the display and draw again once it is ours again.
+//!
+//! The signals for VT switches, and the ones that end us, are read through a
+//! signalfd in the main loop, so the console is always put back into
+//! `KD_TEXT` on the way out.
+
+use std::io;
+use std::os::unix::io::{AsRawFd, FromRawFd, OwnedFd, RawFd};
+
+// From <linux/kd.h> and <linux/vt.h>
+const KDGETMODE: u32 = 0x4B3B;
+const KDSETMODE: u32 = 0x4B3A;
+const KD_TEXT: libc::c_int = 0;
+const KD_GRAPHICS: libc::c_int = 1;
+const VT_GETMODE: u32 = 0x5601;
+const VT_SETMODE: u32 = 0x5602;
+const VT_RELDISP: u32 = 0x5605;
+const VT_PROCESS: libc::c_char = 1;
+const VT_ACKACQ: libc::c_int = 2;
+
+const RELEASE_SIGNAL: libc::c_int = libc::SIGUSR1;
+const ACQUIRE_SIGNAL: libc::c_int = libc::SIGUSR2;
+const QUIT_SIGNALS: [libc::c_int; 3] = [libc::SIGINT, libc::SIGTERM, libc::SIGHUP];
+
+#[repr(C)]
+#[derive(Clone, Copy, Default)]
+struct VtMode {
+    mode: libc::c_char,
+    waitv: libc::c_char,
+    relsig: libc::c_short,
+    acqsig: libc::c_short,
+    frsig: libc::c_short,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Event {
+    /// Another VT is being switched to, stop drawing
+    Release,
+    /// Our VT is shown again, everything has to be redrawn
+    Acquire,
+    /// Interrupted or terminated
+    Quit,
+}
+
+/// The VT we run on, while we are in charge of it
+pub struct Console {
+    fd: RawFd,
+    signals: OwnedFd,
+    /// As it was before, restored on drop
+    saved_mode: VtMode,
+    graphics: bool,
+    active: bool,
+}
+
+fn ioctl<T>(fd: RawFd, request: u32, arg: T) -> io::Result<()> {
+    if unsafe { libc::ioctl(fd, request as _, arg) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+impl Console {
+    /// Take over VT switching for the VT on stdin, `None` if stdin isn't a
+    /// VT (a serial console, SSH, a service without a TTY)
+    pub fn open() -> Option<Self> {
+        let fd = io::stdin().as_raw_fd();
+        let mut kd_mode: libc::c_int = 0;
+        ioctl(fd, KDGETMODE, &mut kd_mode).ok()?;
+        let mut saved_mode = VtMode::default();
+        ioctl(fd, VT_GETMODE, &mut saved_mode).ok()?;
+
+        // Blocked first, their default action would end us
+        let signals = block_signals().ok()?;
+        let mode = VtMode {
+            mode: VT_PROCESS,
+            relsig: RELEASE_SIGNAL as libc::c_short,
+            acqsig: ACQUIRE_SIGNAL as libc::c_short,
+            ..saved_mode
+        };
+        if ioctl(fd, VT_SETMODE, &mode).is_err() {
+            // Ctrl-C has to work as before
+            set_signal_mask(libc::SIG_UNBLOCK).ok()?;
+            return None;
+        }
+
+        Some(Console { fd, signals, saved_mode, graphics: kd_mode == KD_GRAPHICS, active: true })
+    }
+
+    /// Whether our VT is shown, we may only draw while it is
+    pub fn is_active(&self) -> bool {
+        self.active
+    }
+
+    /// Switch between `KD_GRAPHICS`, where fbcon doesn't draw, and `KD_TEXT`,
+    /// returns true if the mode changed
+    ///
+    /// fbcon redraws the text when going back to `KD_TEXT`, what it drew
+    /// before `KD_GRAPHICS` is still on the screen.
+    pub fn set_graphics(&mut self, graphics: bool) -> bool {
+        if graphics == self.graphics {
+            return false;
+        }
+        let mode = if graphics { KD_GRAPHICS } else { KD_TEXT };
+        if ioctl(self.fd, KDSETMODE, mode).is_err() {
+            return false;
+        }
+        self.graphics = graphics;
+        true
+    }
+
+    /// The events that arrived since the last call, without blocking
+    ///
+    /// VT switches are acknowledged right away, so the caller must have let
+    /// go of the screens when it sees `Release`.
+    pub fn events(&mut self) -> Vec<Event> {
+        let mut events = Vec::new();
+        let mut info: libc::signalfd_siginfo = unsafe { std::mem::zeroed() };
+        let size = std::mem::size_of::<libc::signalfd_siginfo>();
+        while unsafe { libc::read(self.signals.as_raw_fd(), &mut info as *mut _ as *mut libc::c_void, size) }
+            == size as isize
+        {
+            match info.ssi_signo as libc::c_int {
+                RELEASE_SIGNAL => events.push(Event::Release),
+                ACQUIRE_SIGNAL => events.push(Event::Acquire),
+                _ => events.push(Event::Quit),
+            }
+        }
+        events
+    }
+
+    /// Let the kernel go on with a switch away from our VT
+    pub fn release(&mut self) {
+        let _ = ioctl(self.fd, VT_RELDISP, 1 as libc::c_int);
+        self.active = false;
+    }
+
+    /// Confirm that our VT is shown again
+    pub fn acquire(&mut self) {
+        let _ = ioctl(self.fd, VT_RELDISP, VT_ACKACQ);
+        self.active = true;
+    }
+}
+
+impl AsRawFd for Console {
+    /// Readable when there are `events`
+    fn as_raw_fd(&self) -> RawFd {
+        self.signals.as_raw_fd()
+    }
+}
+
+impl Drop for Console {
+    fn drop(&mut self) {
+        self.set_graphics(false);
+        let _ = ioctl(self.fd, VT_SETMODE, &self.saved_mode);
+    }
+}
+
+/// The VT switch and quit signals
+fn signal_set() -> libc::sigset_t {
+    unsafe {
+        let mut set: libc::sigset_t = std::mem::zeroed();
+        libc::sigemptyset(&mut set);
+        for signal in [RELEASE_SIGNAL, ACQUIRE_SIGNAL].iter().chain(&QUIT_SIGNALS) {
+            libc::sigaddset(&mut set, *signal);
+        }
+        set
+    }
+}
+
+/// `SIG_BLOCK` or `SIG_UNBLOCK` the signals of `signal_set`
+fn set_signal_mask(how: libc::c_int) -> io::Result<()> {
+    if unsafe { libc::sigprocmask(how, &signal_set(), std::ptr::null_mut()) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// Block the signals of `signal_set`, they are read from the returned
+/// signalfd instead
+fn block_signals() -> io::Result<OwnedFd> {
+    set_signal_mask(libc::SIG_BLOCK)?;
+    let fd = unsafe { libc::signalfd(-1, &signal_set(), libc::SFD_NONBLOCK | libc::SFD_CLOEXEC) };
+    if fd < 0 {
+        let error = io::Error::last_os_error();
+        let _ = set_signal_mask(libc::SIG_UNBLOCK);
+        return Err(error);
+    }
+    Ok(unsafe { OwnedFd::from_raw_fd(fd) })
+}
@@ -0,0 +1,152 @@
+//! Wait for changes to the data shown on screen
+//!
+//! Combines rtnetlink notifications, inotify watches on the files we display
+//! and display hotplug uevents, so the main loop only wakes up when something
+//! may have changed.
+
+use std::ffi::CString;
+use std::io;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::io::{AsRawFd, FromRawFd, OwnedFd, RawFd};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use crate::{netlink, uevent};
+
+/// Events on a watched directory that may mean one of our files changed
+const WATCH_MASK: u32 = libc::IN_CREATE
+    | libc::IN_CLOSE_WRITE
+    | libc::IN_MODIFY
+    | libc::IN_MOVED_TO
+    | libc::IN_MOVED_FROM
+    | libc::IN_DELETE
+    | libc::IN_DELETE_SELF
+    | libc::IN_MOVE_SELF;
+
+/// Delay after the first event so bursts (e.g. address + route) are coalesced
+const SETTLE_TIME: Duration = Duration::from_millis(50);
+
+pub struct Watcher {
+    monitor: netlink::Monitor,
+    inotify: OwnedFd,
+    /// Missing if uevents are unavailable, then only device nodes appearing
+    /// are noticed
+    uevents: Option<uevent::Monitor>,
+    paths: Vec<PathBuf>,
+}
+
+impl Watcher {
+    /// Watch for link/address changes and for changes to any of `paths`
+    ///
+    /// The files don't need to exist yet, we watch the closest existing parent
+    /// directory and move the watch deeper once intermediate directories appear.
+    pub fn new(paths: &[&Path]) -> io::Result<Self> {
+        let monitor = netlink::Monitor::new()?;
+
+        let fd = unsafe { libc::inotify_init1(libc::IN_NONBLOCK | libc::IN_CLOEXEC) };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let inotify = unsafe { OwnedFd::from_raw_fd(fd) };
+
+        let watcher = Watcher {
+            monitor,
+            inotify,
+            uevents: uevent::Monitor::new().ok(),
+            paths: paths.iter().map(|p| p.to_path_buf()).collect(),
+        };
+        watcher.add_watches();
+        Ok(watcher)
+    }
+
+    /// Block until something may have changed, `timeout` elapsed or `wake`
+    /// became readable
+    ///
+    /// `wake` is returned to right away, without waiting for other events to
+    /// settle.
+    pub fn wait(&self, timeout: Duration, wake: Option<RawFd>) -> io::Result<()> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            let ready = self.poll(deadline.saturating_duration_since(Instant::now()), wake)?;
+            if !ready.iter().any(|&r| r) || ready[3] {
+                return Ok(());
+            }
+            // Uevents of other subsystems (USB, block, ...) are not interesting
+            if ready[0] || ready[1] || self.uevents.as_ref().is_some_and(|u| u.drain()) {
+                break;
+            }
+        }
+
+        std::thread::sleep(SETTLE_TIME);
+        self.monitor.drain();
+        if let Some(uevents) = &self.uevents {
+            uevents.drain();
+        }
+        if self.drain_inotify() {
+            // Directories may have been created or replaced, re-resolve watches
+            self.add_watches();
+        }
+        Ok(())
+    }
+
+    /// Which of netlink, inotify, uevents and `wake` became readable
+    fn poll(&self, timeout: Duration, wake: Option<RawFd>) -> io::Result<[bool; 4]> {
+        // A negative fd is ignored by poll()
+        let uevent_fd = self.uevents.as_ref().map_or(-1, |u| u.as_raw_fd());
+        poll([self.monitor.as_raw_fd(), self.inotify.as_raw_fd(), uevent_fd, wake.unwrap_or(-1)], timeout)
+    }
+
+    /// Read and discard all queued inotify events, returns true if there were any
+    fn drain_inotify(&self) -> bool {
+        let mut buf = [0u8; 4096];
+        let mut changed = false;
+        loop {
+            let len = unsafe {
+                libc::read(self.inotify.as_raw_fd(), buf.as_mut_ptr() as *mut libc::c_void, buf.len())
+            };
+            if len > 0 {
+                changed = true;
+                continue;
+            }
+            if len < 0 && io::Error::last_os_error().kind() == io::ErrorKind::Interrupted {
+                continue;
+            }
+            return changed;
+        }
+    }
+
+    /// Watch the parent directory of every path, or its closest existing ancestor
+    ///
+    /// Adding a watch for an already watched directory is a no-op in the
+    /// kernel, so this can be called again whenever the tree changed.
+    fn add_watches(&self) {
+        for path in &self.paths {
+            let Some(dir) = path.ancestors().skip(1).find(|p| p.is_dir()) else {
+                continue;
+            };
+            let Ok(dir) = CString::new(dir.as_os_str().as_bytes()) else {
+                continue;
+            };
+            unsafe {
+                libc::inotify_add_watch(self.inotify.as_raw_fd(), dir.as_ptr(), WATCH_MASK);
+            }
+        }
+    }
+}
+
+/// Which of `fds` became readable within `timeout`, negative fds are skipped
+pub fn poll<const N: usize>(fds: [RawFd; N], timeout: Duration) -> io::Result<[bool; N]> {
+    let mut fds = fds.map(|fd| libc::pollfd { fd, events: libc::POLLIN, revents: 0 });
+    let timeout_ms = timeout.as_millis().min(libc::c_int::MAX as u128) as libc::c_int;
+
+    loop {
+        let ret = unsafe { libc::poll(fds.as_mut_ptr(), N as libc::nfds_t, timeout_ms) };
+        if ret >= 0 {
+            return Ok(fds.map(|fd| fd.revents != 0));
+        }
+        let err = io::Error::last_os_error();
+        if err.kind() != io::ErrorKind::Interrupted {
+            return Err(err);
+        }
+    }
+}